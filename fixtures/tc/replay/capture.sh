@@ -0,0 +1,17 @@
+#!/bin/sh
+# Print the netem dumps of netem.py with the tc of this system, into DIR.
+set -e
+here=$(dirname "$0")
+out=$1
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+cc -shared -fPIC -O2 -Wall -o "$tmp/replay.so" "$here/replay.c" -ldl
+python3 "$here/netem.py" "$tmp"
+for dump in "$tmp"/*.bin; do
+    name=$(basename "$dump" .bin)
+    TC_REPLAY=$dump LD_PRELOAD=$tmp/replay.so tc qdisc show dev lo >"$out/$name.txt"
+    # older versions don't know -j
+    TC_REPLAY=$dump LD_PRELOAD=$tmp/replay.so tc -j qdisc show dev lo >"$out/$name.json" ||
+        rm "$out/$name.json"
+done
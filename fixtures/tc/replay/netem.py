@@ -0,0 +1,109 @@
+#!/usr/bin/env python3
+"""Write RTM_NEWQDISC messages of netem qdiscs laid out like the dumps of
+netem_dump() in the kernel's sch_netem.c, for replay.c to answer tc with.
+
+Usage: netem.py DIR, which gets a <case>.bin file per case below.
+"""
+import os
+import struct
+import sys
+
+RTM_NEWQDISC = 36
+TCA_KIND, TCA_OPTIONS = 1, 2
+TC_H_ROOT = 0xFFFFFFFF
+(TCA_NETEM_CORR, TCA_NETEM_REORDER, TCA_NETEM_CORRUPT, TCA_NETEM_LOSS,
+ TCA_NETEM_RATE, TCA_NETEM_ECN, TCA_NETEM_RATE64) = 1, 3, 4, 5, 6, 7, 8
+TCA_NETEM_LATENCY64, TCA_NETEM_JITTER64, TCA_NETEM_SLOT = 10, 11, 12
+NETEM_LOSS_GI, NETEM_LOSS_GE = 1, 2
+INT_MAX, UINT32_MAX = 0x7FFFFFFF, 0xFFFFFFFF
+LOOPBACK = 1
+
+
+def attr(kind, payload):
+    length = 4 + len(payload)
+    return struct.pack("=HH", length, kind) + payload + b"\0" * (-length % 4)
+
+
+def percent(value):
+    """tc's get_percent()"""
+    return round(value / 100 * UINT32_MAX)
+
+
+def ms(value):
+    return round(value * 1_000_000)
+
+
+def netem(
+    latency=0, jitter=0, limit=1000, loss=0, gap=0, duplicate=0,
+    delay_corr=0, loss_corr=0, dup_corr=0, reorder=(0, 0), corrupt=(0, 0),
+    rate=(0, 0, 0, 0), ecn=False, gi=None, ge=None, slot=None,
+):
+    # PSCHED_NS2TICKS()
+    qopt = struct.pack(
+        "=6I", min(latency >> 6, UINT32_MAX), limit, loss, gap, duplicate,
+        min(jitter >> 6, UINT32_MAX),
+    )
+    options = qopt
+    options += attr(TCA_NETEM_LATENCY64, struct.pack("=q", latency))
+    options += attr(TCA_NETEM_JITTER64, struct.pack("=q", jitter))
+    options += attr(TCA_NETEM_CORR, struct.pack("=3I", delay_corr, loss_corr, dup_corr))
+    options += attr(TCA_NETEM_REORDER, struct.pack("=2I", *reorder))
+    options += attr(TCA_NETEM_CORRUPT, struct.pack("=2I", *corrupt))
+    bytes_per_second, overhead, cellsize, cell_overhead = rate
+    if bytes_per_second >= 1 << 32:
+        options += attr(TCA_NETEM_RATE64, struct.pack("=Q", bytes_per_second))
+        bytes_per_second = UINT32_MAX
+    options += attr(
+        TCA_NETEM_RATE, struct.pack("=IiIi", bytes_per_second, overhead, cellsize, cell_overhead)
+    )
+    if ecn:
+        options += attr(TCA_NETEM_ECN, struct.pack("=I", 1))
+    if gi:
+        options += attr(TCA_NETEM_LOSS, attr(NETEM_LOSS_GI, struct.pack("=5I", *gi)))
+    if ge:
+        options += attr(TCA_NETEM_LOSS, attr(NETEM_LOSS_GE, struct.pack("=4I", *ge)))
+    if slot:
+        min_delay, max_delay, packets, bytes_, dist_delay, dist_jitter = slot
+        options += attr(
+            TCA_NETEM_SLOT,
+            struct.pack(
+                "=qqiiqq", min_delay, max_delay,
+                0 if packets == INT_MAX else packets, 0 if bytes_ == INT_MAX else bytes_,
+                dist_delay, dist_jitter,
+            ),
+        )
+
+    tcmsg = struct.pack("=BxxxiIII", 0, LOOPBACK, 0x80010000, TC_H_ROOT, 2)
+    body = tcmsg + attr(TCA_KIND, b"netem\0") + attr(TCA_OPTIONS, options)
+    return struct.pack("=IHHII", 16 + len(body), RTM_NEWQDISC, 0, 0, 0) + body
+
+
+CASES = {
+    # tc qdisc add dev lo root netem limit 1000 delay 10ms 2ms 50% distribution normal
+    #   loss random 1% 25% duplicate 0.5% reorder 10% 50% gap 5 corrupt 0.1%
+    #   rate 1500kbit 20 53 -5 slot 1ms 8ms packets 42 bytes 65536 ecn
+    "netem": netem(
+        latency=ms(10), jitter=ms(2), delay_corr=percent(50),
+        loss=percent(1), loss_corr=percent(25), duplicate=percent(0.5),
+        reorder=(percent(10), percent(50)), gap=5, corrupt=(percent(0.1), 0),
+        rate=(1_500_000 // 8, 20, 53, -5), ecn=True,
+        slot=(ms(1), ms(8), 42, 65536, 0, 0),
+    ),
+    # tc qdisc add dev lo root netem delay 1.5s 500us
+    #   loss gemodel 1% 30% 90% 0.5% slot distribution normal 10ms 2ms bytes 65536
+    "netem-gemodel": netem(
+        latency=ms(1500), jitter=ms(0.5),
+        ge=(percent(1), percent(30), UINT32_MAX - percent(90), percent(0.5)),
+        slot=(0, 0, INT_MAX, 65536, ms(10), ms(2)),
+    ),
+    # tc qdisc add dev lo root netem loss state 1% 30% 2% 40% 0.5% ecn
+    "netem-state": netem(
+        gi=(percent(1), percent(30), percent(2), percent(0.5), percent(40)), ecn=True,
+    ),
+    # tc qdisc add dev lo root netem limit 5000 rate 40gbit
+    "netem-rate64": netem(limit=5000, rate=(40_000_000_000 // 8, 0, 0, 0)),
+}
+
+for name, message in CASES.items():
+    with open(os.path.join(sys.argv[1], name + ".bin"), "wb") as f:
+        f.write(message)
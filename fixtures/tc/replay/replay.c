@@ -0,0 +1,96 @@
+/*
+ * Replay qdisc dumps to tc: LD_PRELOAD it with TC_REPLAY set to a file of
+ * RTM_NEWQDISC messages, and the RTM_GETQDISC dump tc requests gets them as
+ * the kernel's answer.
+ */
+#define _GNU_SOURCE
+#include <dlfcn.h>
+#include <linux/netlink.h>
+#include <linux/rtnetlink.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/socket.h>
+
+static int dump_fd = -1;
+static char *pending;
+static size_t pending_len;
+
+static void load(int fd, unsigned int seq)
+{
+	struct sockaddr_nl local;
+	socklen_t local_len = sizeof(local);
+	FILE *f = fopen(getenv("TC_REPLAY"), "rb");
+	struct nlmsghdr *h, *done;
+	long len;
+
+	if (!f) {
+		perror("TC_REPLAY");
+		exit(1);
+	}
+	fseek(f, 0, SEEK_END);
+	len = ftell(f);
+	rewind(f);
+	pending = malloc(len + NLMSG_LENGTH(sizeof(int)));
+	if (fread(pending, 1, len, f) != (size_t)len)
+		exit(1);
+	fclose(f);
+	if (getsockname(fd, (struct sockaddr *)&local, &local_len) < 0)
+		exit(1);
+	for (h = (struct nlmsghdr *)pending; (char *)h < pending + len;
+	     h = (struct nlmsghdr *)((char *)h + NLMSG_ALIGN(h->nlmsg_len))) {
+		h->nlmsg_seq = seq;
+		h->nlmsg_pid = local.nl_pid;
+		h->nlmsg_flags |= NLM_F_MULTI;
+	}
+	done = (struct nlmsghdr *)(pending + len);
+	memset(done, 0, NLMSG_LENGTH(sizeof(int)));
+	done->nlmsg_len = NLMSG_LENGTH(sizeof(int));
+	done->nlmsg_type = NLMSG_DONE;
+	done->nlmsg_flags = NLM_F_MULTI;
+	done->nlmsg_seq = seq;
+	done->nlmsg_pid = local.nl_pid;
+	pending_len = len + done->nlmsg_len;
+}
+
+ssize_t sendmsg(int fd, const struct msghdr *msg, int flags)
+{
+	static ssize_t (*real)(int, const struct msghdr *, int);
+	struct nlmsghdr *h = msg->msg_iov[0].iov_base;
+
+	if (!real)
+		real = dlsym(RTLD_NEXT, "sendmsg");
+	if (msg->msg_iov[0].iov_len >= sizeof(*h) &&
+	    h->nlmsg_type == RTM_GETQDISC && (h->nlmsg_flags & NLM_F_DUMP)) {
+		dump_fd = fd;
+		load(fd, h->nlmsg_seq);
+		return msg->msg_iov[0].iov_len;
+	}
+	return real(fd, msg, flags);
+}
+
+ssize_t recvmsg(int fd, struct msghdr *msg, int flags)
+{
+	static ssize_t (*real)(int, struct msghdr *, int);
+	struct sockaddr_nl *nl = msg->msg_name;
+	size_t n;
+
+	if (!real)
+		real = dlsym(RTLD_NEXT, "recvmsg");
+	if (fd != dump_fd || !pending)
+		return real(fd, msg, flags);
+	if (nl) {
+		memset(nl, 0, sizeof(*nl));
+		nl->nl_family = AF_NETLINK;
+		msg->msg_namelen = sizeof(*nl);
+	}
+	n = pending_len < msg->msg_iov[0].iov_len ? pending_len : msg->msg_iov[0].iov_len;
+	memcpy(msg->msg_iov[0].iov_base, pending, n);
+	msg->msg_flags = 0;
+	if (!(flags & MSG_PEEK)) {
+		free(pending);
+		pending = NULL;
+		dump_fd = -1;
+	}
+	return pending_len;
+}
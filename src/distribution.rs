@@ -0,0 +1,242 @@
+/// Custom delay distribution tables
+///
+/// A port of iproute2's `maketable` and `stats` tools (netem/maketable.c and
+/// netem/stats.c), so that a table can be built from measured samples on a
+/// router that doesn't ship them. The table is written as `NAME.dist` into
+/// the directory tc loads distributions from, and `distribution NAME` of a
+/// delay refers to it.
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// entries of a netem distribution table
+const TABLE_SIZE: usize = 4096;
+/// NETEM_DIST_SCALE, table values are standard deviations scaled by this
+const TABLE_FACTOR: f64 = 8192.0;
+/// values are scaled by TABLE_FACTOR and must fit in a short, so there is
+/// no need to look at a larger domain than this (in standard deviations)
+const DOMAIN: i32 = i16::MAX as i32 / TABLE_FACTOR as i32 + 1;
+const GRANULARITY: i32 = 50000;
+const DIST_SIZE: usize = (DOMAIN * GRANULARITY * 2) as usize;
+
+/// tc looks for `NAME.dist` in `$TC_LIB_DIR`, falling back to the directory
+/// it was built with, which is /usr/lib/tc on OpenWrt.
+pub static TC_LIB_DIR: Lazy<PathBuf> = Lazy::new(|| {
+    std::env::var_os("TC_LIB_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/usr/lib/tc"))
+});
+
+pub static NAME_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^[A-Za-z][\w\-]*$").expect("Failed to create regex of distribution name")
+});
+
+/// the tables shipped with iproute2, never overwrite them
+const BUILTIN: [&str; 5] = [
+    "uniform",
+    "normal",
+    "pareto",
+    "paretonormal",
+    "experimental",
+];
+
+/// Statistics of the samples, as printed by iproute2's `stats`.
+///
+/// They are the parameters to use with the table:
+/// `delay MEAN STDDEV CORRELATION distribution NAME`
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Statistics {
+    pub mean: f64,
+    pub stddev: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation: Option<f64>,
+}
+
+pub struct Table {
+    pub statistics: Statistics,
+    entries: Vec<i16>,
+}
+
+impl Table {
+    /// Build a table from samples in the order they were measured and from
+    /// histogram buckets of `(value, count)`. The correlation can only be
+    /// computed from ordered samples.
+    pub fn new(samples: &[f64], histogram: &[(f64, u64)]) -> anyhow::Result<Self> {
+        let values = samples
+            .iter()
+            .map(|v| (*v, 1))
+            .chain(histogram.iter().copied())
+            .filter(|(_, count)| *count > 0)
+            .collect::<Vec<(f64, u64)>>();
+
+        if let Some((value, _)) = values.iter().find(|(v, _)| !v.is_finite()) {
+            return Err(anyhow::anyhow!("Invalid sample: {}", value));
+        }
+
+        let n: f64 = values.iter().map(|(_, count)| *count as f64).sum();
+        if n < 2.0 {
+            return Err(anyhow::anyhow!("At least 2 samples are needed"));
+        }
+
+        let sum: f64 = values.iter().map(|(v, count)| v * *count as f64).sum();
+        let sum_square: f64 = values.iter().map(|(v, count)| v * v * *count as f64).sum();
+        let mean = sum / n;
+        let stddev = ((sum_square - n * mean * mean) / (n - 1.0)).max(0.0).sqrt();
+        if stddev == 0.0 {
+            return Err(anyhow::anyhow!("Samples must not all be the same"));
+        }
+
+        let correlation = if samples.len() > 1 {
+            let (top, sigma2) = samples.windows(2).fold((0.0, 0.0), |(top, sigma2), w| {
+                (
+                    top + (w[1] - mean) * (w[0] - mean),
+                    sigma2 + (w[0] - mean) * (w[0] - mean),
+                )
+            });
+            Some(if sigma2 > 0.0 {
+                top / sigma2 * 100.0
+            } else {
+                0.0
+            })
+        } else {
+            None
+        };
+
+        // makedist + cumulativedist
+        let mut table = vec![0u64; DIST_SIZE];
+        for (value, count) in &values {
+            let input = (value - mean) / stddev;
+            let index = ((input + DOMAIN as f64) * GRANULARITY as f64).round();
+            let index = (index.max(0.0) as usize).min(DIST_SIZE - 1);
+            table[index] += count;
+        }
+
+        let mut total = 0;
+        for entry in table.iter_mut() {
+            total += *entry;
+            *entry = total;
+        }
+
+        // inverttable
+        let mut entries = vec![i16::MIN; TABLE_SIZE];
+        for (i, cumulative) in table.iter().enumerate() {
+            let findex = i as f64 / GRANULARITY as f64 - DOMAIN as f64;
+            let fvalue = *cumulative as f64 / total as f64;
+            let index = ((fvalue * TABLE_SIZE as f64).round() as usize).min(TABLE_SIZE - 1);
+            let value = (findex * TABLE_FACTOR).round() as i32;
+            entries[index] = value.clamp(i16::MIN as i32 + 1, i16::MAX as i32) as i16;
+        }
+
+        // interpolatetable
+        let (mut last, mut last_index) = (i16::MIN as i32, -1i32);
+        for i in 0..TABLE_SIZE {
+            if entries[i] == i16::MIN {
+                let (next, next_index) = match entries[i..].iter().position(|e| *e != i16::MIN) {
+                    Some(j) => (entries[i + j] as i32, (i + j) as i32),
+                    None => (i16::MAX as i32, TABLE_SIZE as i32),
+                };
+                entries[i] = (last
+                    + (i as i32 - last_index) * (next - last) / (next_index - last_index))
+                    as i16;
+            } else {
+                last = entries[i] as i32;
+                last_index = i as i32;
+            }
+        }
+
+        Ok(Table {
+            statistics: Statistics {
+                mean,
+                stddev,
+                correlation,
+            },
+            entries,
+        })
+    }
+
+    /// The table in the format of the `.dist` files read by tc.
+    pub fn to_dist_string(&self) -> String {
+        let mut s =
+            String::from("# This is the distribution table for the experimental distribution.\n");
+
+        for line in self.entries.chunks(8) {
+            let line = line
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<String>>()
+                .join(" ");
+            s.push_str(&line);
+            s.push('\n');
+        }
+
+        s
+    }
+
+    /// Write the table to `TC_LIB_DIR/NAME.dist`.
+    pub async fn install(&self, name: &str) -> anyhow::Result<PathBuf> {
+        if !NAME_REGEX.is_match(name) {
+            return Err(anyhow::anyhow!("Invalid distribution name: '{}'", name));
+        }
+
+        if BUILTIN.contains(&name) {
+            return Err(anyhow::anyhow!(
+                "'{}' is a built-in distribution of tc",
+                name
+            ));
+        }
+
+        tokio::fs::create_dir_all(&*TC_LIB_DIR)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to create {}: {}", TC_LIB_DIR.display(), e))?;
+
+        let path = TC_LIB_DIR.join(format!("{}.dist", name));
+        let temp = TC_LIB_DIR.join(format!(".{}.dist.tmp", name));
+        tokio::fs::write(&temp, self.to_dist_string())
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", temp.display(), e))?;
+        tokio::fs::rename(&temp, &path)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to install {}: {}", path.display(), e))?;
+
+        log::info!("Installed distribution table {}", path.display());
+
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_table() -> anyhow::Result<()> {
+        let samples = (0..1000)
+            .map(|i| 20.0 + ((i * 7919) % 100) as f64 / 10.0)
+            .collect::<Vec<f64>>();
+        let table = Table::new(&samples, &[])?;
+
+        assert!((table.statistics.mean - 24.95).abs() < 1e-9);
+        assert!((table.statistics.stddev - 2.8880).abs() < 1e-3);
+        assert!(table.statistics.correlation.is_some());
+
+        assert_eq!(table.entries.len(), TABLE_SIZE);
+        assert!(table.entries.windows(2).all(|w| w[0] <= w[1]));
+        // a uniform distribution starts about sqrt(3) standard deviations
+        // below its mean, which is in the middle of the table
+        assert!((table.entries[0] as f64 / TABLE_FACTOR + 1.73).abs() < 0.05);
+        assert!((table.entries[TABLE_SIZE / 2] as f64 / TABLE_FACTOR).abs() < 0.05);
+
+        let dist = table.to_dist_string();
+        assert_eq!(dist.lines().count(), 1 + TABLE_SIZE / 8);
+
+        let histogram = Table::new(&[], &[(10.0, 3), (20.0, 1)])?;
+        assert_eq!(histogram.statistics.mean, 12.5);
+        assert_eq!(histogram.statistics.correlation, None);
+
+        assert!(Table::new(&[1.0], &[]).is_err());
+        assert!(Table::new(&[], &[(1.0, 5)]).is_err());
+
+        Ok(())
+    }
+}
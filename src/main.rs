@@ -1,15 +1,21 @@
-use crate::netem::{NetEm, Output};
+use crate::netem::{Backend, Executor, NetEm, Output};
+use axum::extract::ConnectInfo;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::routing::{get_service, post};
-use axum::{Json, Router, Server};
+use axum::{Extension, Json, Router, Server};
 use clap::Parser;
 use log::LevelFilter;
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
 use tower_http::services::ServeDir;
 
+mod distribution;
 mod netem;
+mod netlink;
 
 #[derive(Debug, Parser)]
 #[clap(name = "taco")]
@@ -20,6 +26,31 @@ struct Opts {
     web: PathBuf,
     #[clap(short, long, default_value = "INFO")]
     log_level: LevelFilter,
+    /// change qdiscs with the tc binary or through rtnetlink
+    #[clap(short, long, value_enum, default_value = "tc")]
+    backend: Backend,
+    /// interfaces Set refuses to impair without force, besides the one the
+    /// API is called through
+    #[clap(long = "protect", value_name = "INTERFACE")]
+    protected: Vec<String>,
+    /// keep the impairments when shutting down
+    #[clap(long)]
+    keep: bool,
+    /// file user-defined profiles are kept in, they are lost on restart
+    /// without one
+    #[clap(long, value_name = "FILE")]
+    profiles: Option<PathBuf>,
+    /// file the impairments interfaces should have are kept in, and put
+    /// back from at startup
+    #[clap(long, value_name = "FILE")]
+    state: Option<PathBuf>,
+    /// seconds between checks for impairments changed behind taco's back,
+    /// 0 to never check
+    #[clap(long, value_name = "SECONDS", default_value = "30")]
+    drift_check: u64,
+    /// put back the impairments interfaces drifted from
+    #[clap(long)]
+    repair: bool,
 }
 
 #[tokio::main]
@@ -28,27 +59,816 @@ async fn main() -> anyhow::Result<()> {
         port,
         web,
         log_level,
+        backend,
+        protected,
+        keep,
+        profiles,
+        state,
+        drift_check,
+        repair,
     } = Opts::parse();
 
     env_logger::builder().filter_level(log_level).try_init()?;
-
-    let router = Router::new()
-        .route("/api", post(api))
-        .fallback(get_service(ServeDir::new(web)).handle_error(handle_error));
+    let executor = backend.executor();
+    netem::protect(executor.as_ref(), protected);
+    if let Some(profiles) = profiles {
+        netem::load_profiles(executor.as_ref(), profiles).await?;
+    }
+    if let Some(state) = state {
+        netem::load_state(executor.as_ref(), state).await?;
+    }
+    let drift = (drift_check > 0)
+        .then(|| netem::watch_drift(executor.clone(), Duration::from_secs(drift_check), repair));
+    let router = router(executor.clone(), web);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
-    log::info!("Taco server is running on {}...", port);
+    log::info!(
+        "Taco server is running on {} with the {:?} backend...",
+        port,
+        backend
+    );
+    let mut terminate = signal(SignalKind::terminate())?;
     Server::bind(&addr)
-        .serve(router.into_make_service())
+        .serve(router.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(async move {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            log::info!("Shutting down...");
+        })
         .await?;
 
+    // don't put back what is being reset
+    if let Some(drift) = drift {
+        drift.abort();
+    }
+    if !keep {
+        netem::reset_all(executor.as_ref()).await;
+    }
     Ok(())
 }
 
+fn router(executor: Arc<dyn Executor>, web: PathBuf) -> Router {
+    Router::new()
+        .route("/api", post(api))
+        .fallback(get_service(ServeDir::new(web)).handle_error(handle_error))
+        .layer(Extension(executor))
+}
+
 async fn handle_error(err: std::io::Error) -> impl IntoResponse {
     (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
 }
 
-async fn api(Json(netem): Json<NetEm>) -> Json<Output> {
-    Json(netem.execute().await)
+async fn api(
+    Extension(executor): Extension<Arc<dyn Executor>>,
+    ConnectInfo(caller): ConnectInfo<SocketAddr>,
+    Json(netem): Json<NetEm>,
+) -> Json<Output> {
+    Json(netem.execute(&executor, Some(caller.ip())).await)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::netem::fake::Fake;
+    use axum::body::Body;
+    use axum::http::Request;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    /// The address of the client calling the API in tests
+    const CALLER: ([u8; 4], u16) = ([192, 168, 1, 100], 50000);
+
+    async fn call(router: &Router, request: Value) -> Value {
+        let mut request = Request::post("/api")
+            .header("content-type", "application/json")
+            .body(Body::from(request.to_string()))
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(CALLER)));
+        let response = router.clone().oneshot(request).await.unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// Move the paused clock on, and let the tasks it wakes run until they
+    /// wait again: it only moves on its own once every task waits.
+    async fn advance(duration: Duration) {
+        tokio::time::advance(duration).await;
+        tokio::time::sleep(Duration::from_nanos(1)).await;
+    }
+
+    #[tokio::test]
+    async fn test_api() {
+        let router = router(
+            Arc::new(Fake::new(&["br-lan", "eth0"])),
+            PathBuf::from("web"),
+        );
+
+        let list = call(&router, json!({"type": "list"})).await;
+        assert_eq!(
+            list,
+            json!({"status": "interfaces", "list": ["br-lan", "eth0"], "ifbs": []})
+        );
+
+        let controls = json!({"delay": {"time": 100.0, "jitter": 10.0}, "loss": {"percent": 1.0, "ecn": false}});
+        let set = json!({"type": "set", "interface": "br-lan", "controls": controls});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+
+        let show = call(&router, json!({"type": "show", "interface": "br-lan"})).await;
+        assert_eq!(
+            show,
+            json!({"status": "controls", "interface": "br-lan", "controls": controls})
+        );
+
+        let reset = json!({"type": "reset", "interface": "br-lan"});
+        assert_eq!(call(&router, reset.clone()).await, json!({"status": "ok"}));
+        let again = call(&router, reset).await;
+        assert_eq!(again["status"], "error");
+
+        let missing = call(&router, json!({"type": "show", "interface": "wlan0"})).await;
+        assert_eq!(
+            missing,
+            json!({"status": "error", "description": "Cannot find device \"wlan0\""})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ingress() {
+        let fake = Arc::new(Fake::new(&["br-lan", "eth0"]));
+        let router = router(fake.clone(), PathBuf::from("web"));
+
+        let controls = json!({"delay": {"time": 50.0}});
+        let set = json!({"type": "set", "interface": "eth0", "controls": controls, "direction": "ingress"});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        assert_eq!(fake.ingress("eth0").as_deref(), Some("ifb-eth0"));
+
+        let list = call(&router, json!({"type": "list"})).await;
+        assert_eq!(list["list"], json!(["br-lan", "eth0"]));
+        assert_eq!(
+            list["ifbs"],
+            json!([{"name": "ifb-eth0", "interface": "eth0"}])
+        );
+
+        let show = call(&router, json!({"type": "show", "interface": "eth0"})).await;
+        assert_eq!(show["controls"], json!({}));
+        assert_eq!(show["ingress"], json!({"controls": controls}));
+
+        // only ingress is impaired, reset still cleans everything up
+        let reset = json!({"type": "reset", "interface": "eth0"});
+        assert_eq!(call(&router, reset).await, json!({"status": "ok"}));
+        assert_eq!(fake.ingress("eth0"), None);
+        let list = call(&router, json!({"type": "list"})).await;
+        assert_eq!(list["ifbs"], json!([]));
+
+        // no IFB device is left behind for a missing interface
+        let set =
+            json!({"type": "set", "interface": "wlan0", "controls": controls, "direction": "both"});
+        assert_eq!(call(&router, set).await["status"], "error");
+        let set = json!({"type": "set", "interface": "wlan0", "controls": controls, "direction": "ingress"});
+        assert_eq!(call(&router, set).await["status"], "error");
+        let list = call(&router, json!({"type": "list"})).await;
+        assert_eq!(list["ifbs"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_match() {
+        let router = router(Arc::new(Fake::new(&["br-lan"])), PathBuf::from("web"));
+
+        let controls = json!({"delay": {"time": 200.0}});
+        let filter = json!({"dst": "10.0.1.0/24", "dport": "8000-8100"});
+        let set =
+            json!({"type": "set", "interface": "br-lan", "controls": controls, "match": filter});
+        assert_eq!(call(&router, set.clone()).await, json!({"status": "ok"}));
+        // filters are replaced, not added to
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+
+        let show = call(&router, json!({"type": "show", "interface": "br-lan"})).await;
+        assert_eq!(show["controls"], json!({}));
+        assert_eq!(
+            show["classes"],
+            json!([{"filters": [filter], "controls": controls}])
+        );
+
+        // a Set without a match impairs everything again
+        let set = json!({"type": "set", "interface": "br-lan", "controls": controls});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        let show = call(&router, json!({"type": "show", "interface": "br-lan"})).await;
+        assert_eq!(show["controls"], controls);
+        assert_eq!(show.get("classes"), None);
+
+        let filter = json!({"src": "10.0.1.0/24", "dst": "fd00::/8"});
+        let set =
+            json!({"type": "set", "interface": "br-lan", "controls": controls, "match": filter});
+        assert_eq!(call(&router, set).await["status"], "error");
+
+        let set = json!({"type": "set", "interface": "br-lan", "controls": controls, "match": {"protocol": "udp"}});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        let reset = json!({"type": "reset", "interface": "br-lan"});
+        assert_eq!(call(&router, reset.clone()).await, json!({"status": "ok"}));
+        assert_eq!(call(&router, reset).await["status"], "error");
+    }
+
+    #[tokio::test]
+    async fn test_classes() {
+        let router = router(Arc::new(Fake::new(&["br-lan"])), PathBuf::from("web"));
+
+        let slow = json!({"delay": {"time": 200.0}, "loss": {"percent": 2.0, "ecn": false}});
+        let fast = json!({"delay": {"time": 50.0}});
+        let lan1 = json!({"dst": "10.0.1.0/24"});
+        let lan2 = json!({"dst": "10.0.2.0/24"});
+        for (name, controls, filter) in [("lan1", &fast, &lan1), ("lan2", &fast, &lan2)] {
+            let set = json!({"type": "set_class", "interface": "br-lan", "name": name, "controls": controls, "match": filter});
+            assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        }
+        // updating a class leaves the other one alone
+        let set = json!({"type": "set_class", "interface": "br-lan", "name": "lan1", "controls": slow, "match": lan1});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+
+        let show = call(&router, json!({"type": "show", "interface": "br-lan"})).await;
+        assert_eq!(
+            show["classes"],
+            json!([
+                {"name": "lan1", "filters": [lan1], "controls": slow},
+                {"name": "lan2", "filters": [lan2], "controls": fast},
+            ])
+        );
+
+        let remove = json!({"type": "remove_class", "interface": "br-lan", "name": "lan1"});
+        assert_eq!(call(&router, remove.clone()).await, json!({"status": "ok"}));
+        assert_eq!(call(&router, remove).await["status"], "error");
+        let show = call(&router, json!({"type": "show", "interface": "br-lan"})).await;
+        assert_eq!(
+            show["classes"],
+            json!([{"name": "lan2", "filters": [lan2], "controls": fast}])
+        );
+
+        // the last class takes taco's prio with it
+        let remove = json!({"type": "remove_class", "interface": "br-lan", "name": "lan2"});
+        assert_eq!(call(&router, remove).await, json!({"status": "ok"}));
+        let reset = json!({"type": "reset", "interface": "br-lan"});
+        assert_eq!(call(&router, reset).await["status"], "error");
+    }
+
+    #[tokio::test]
+    async fn test_client() {
+        let fake = Arc::new(Fake::new(&["br-lan"]));
+        let router = router(fake.clone(), PathBuf::from("web"));
+
+        let controls = json!({"delay": {"time": 300.0}});
+        let mac = "a4:83:e7:12:34:56";
+        let set = json!({"type": "set_client", "interface": "br-lan", "mac": "A4-83-E7-12-34-56", "controls": controls});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        assert_eq!(fake.ingress("br-lan").as_deref(), Some("ifb-br-lan"));
+
+        let show = json!({"type": "show_client", "interface": "br-lan", "mac": mac});
+        assert_eq!(
+            call(&router, show.clone()).await,
+            json!({"status": "client", "interface": "br-lan", "mac": mac, "egress": controls, "ingress": controls})
+        );
+        let show_interface = call(&router, json!({"type": "show", "interface": "br-lan"})).await;
+        assert_eq!(
+            show_interface["classes"],
+            json!([{"name": mac, "filters": [{"dst_mac": mac}], "controls": controls}])
+        );
+        assert_eq!(
+            show_interface["ingress"]["classes"],
+            json!([{"name": mac, "filters": [{"src_mac": mac}], "controls": controls}])
+        );
+
+        let reset = json!({"type": "reset_client", "interface": "br-lan", "mac": mac});
+        assert_eq!(call(&router, reset.clone()).await, json!({"status": "ok"}));
+        assert_eq!(fake.ingress("br-lan"), None);
+        assert_eq!(
+            call(&router, show).await,
+            json!({"status": "client", "interface": "br-lan", "mac": mac})
+        );
+        assert_eq!(call(&router, reset).await["status"], "error");
+    }
+
+    #[tokio::test]
+    async fn test_clients() {
+        let fake = Arc::new(Fake::new(&["br-guest"]));
+        fake.add_client("a4:83:e7:12:34:56", "192.168.1.100", "br-guest");
+        fake.add_client("00:11:22:33:44:55", "192.168.1.50", "br-guest");
+        let router = router(fake, PathBuf::from("web"));
+
+        // the API is called through br-guest
+        let set = json!({"type": "set_client", "interface": "br-guest", "mac": "a4:83:e7:12:34:56", "controls": {"delay": {"time": 300.0}}, "direction": "egress"});
+        assert_eq!(call(&router, set).await["status"], "error");
+        let set = json!({"type": "set_client", "interface": "br-guest", "mac": "a4:83:e7:12:34:56", "controls": {"delay": {"time": 300.0}}, "direction": "egress", "force": true});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+
+        let clients = call(&router, json!({"type": "clients"})).await;
+        assert_eq!(
+            clients,
+            json!({"status": "clients", "list": [
+                {"mac": "a4:83:e7:12:34:56", "ip": "192.168.1.100", "interface": "br-guest", "impaired": true},
+                {"mac": "00:11:22:33:44:55", "ip": "192.168.1.50", "interface": "br-guest", "impaired": false},
+            ]})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_me() {
+        let fake = Arc::new(Fake::new(&["wlan0"]));
+        let router = router(fake.clone(), PathBuf::from("web"));
+
+        let controls = json!({"loss": {"percent": 10.0, "ecn": false}});
+        let set = json!({"type": "set_me", "controls": controls});
+        // the caller isn't in the ARP table
+        assert_eq!(call(&router, set.clone()).await["status"], "error");
+
+        fake.add_client("a4:83:e7:12:34:56", "192.168.1.100", "wlan0");
+        assert_eq!(
+            call(&router, set).await,
+            json!({"status": "client", "interface": "wlan0", "mac": "a4:83:e7:12:34:56", "egress": controls, "ingress": controls})
+        );
+
+        let reset = json!({"type": "reset_me"});
+        assert_eq!(call(&router, reset.clone()).await, json!({"status": "ok"}));
+        assert_eq!(fake.ingress("wlan0"), None);
+        assert_eq!(call(&router, reset).await["status"], "error");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ttl() {
+        let router = router(Arc::new(Fake::new(&["wan"])), PathBuf::from("web"));
+
+        let before = json!({"delay": {"time": 20.0}});
+        let set = json!({"type": "set", "interface": "wan", "controls": before});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+
+        let set = json!({"type": "set", "interface": "wan", "controls": {"loss": {"percent": 100.0, "ecn": false}}, "ttl": 60});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        let list = call(&router, json!({"type": "expirations"})).await;
+        assert_eq!(list["list"][0]["interface"], "wan");
+        assert!(list["list"][0]["remaining"].as_f64().unwrap() > 59.0);
+
+        let extend = json!({"type": "extend", "interface": "wan", "seconds": 60});
+        assert_eq!(call(&router, extend).await, json!({"status": "ok"}));
+        let list = call(&router, json!({"type": "expirations"})).await;
+        assert!(list["list"][0]["remaining"].as_f64().unwrap() > 119.0);
+
+        let cancel = json!({"type": "cancel", "interface": "wan"});
+        assert_eq!(call(&router, cancel.clone()).await, json!({"status": "ok"}));
+        assert_eq!(call(&router, cancel).await["status"], "error");
+
+        // a short TTL puts the impairment before the Set back
+        let set = json!({"type": "set", "interface": "wan", "controls": {"delay": {"time": 500.0}}, "ttl": 0.05});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        advance(Duration::from_millis(300)).await;
+        let show = call(&router, json!({"type": "show", "interface": "wan"})).await;
+        assert_eq!(
+            show["controls"],
+            json!({"loss": {"percent": 100.0, "ecn": false}})
+        );
+        let list = call(&router, json!({"type": "expirations"})).await;
+        assert_eq!(list["list"], json!([]));
+
+        // and classes too
+        let class = json!({"type": "set_class", "interface": "wan", "name": "dns", "controls": before, "match": {"dport": 53}});
+        assert_eq!(call(&router, class).await, json!({"status": "ok"}));
+        let before = call(&router, json!({"type": "show", "interface": "wan"})).await;
+        let set = json!({"type": "set", "interface": "wan", "controls": {"delay": {"time": 500.0}}, "ttl": 0.05, "direction": "both"});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        advance(Duration::from_millis(300)).await;
+        let show = call(&router, json!({"type": "show", "interface": "wan"})).await;
+        assert_eq!(show, before);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lease() {
+        let router = router(Arc::new(Fake::new(&["wan2"])), PathBuf::from("web"));
+
+        let heartbeat = json!({"type": "heartbeat", "interface": "wan2"});
+        assert_eq!(call(&router, heartbeat.clone()).await["status"], "error");
+
+        let set = json!({"type": "set", "interface": "wan2", "controls": {"delay": {"time": 100.0}}, "lease": 0.2});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        let list = call(&router, json!({"type": "expirations"})).await;
+        assert_eq!(list["list"][0]["lease"], 0.2);
+
+        // heartbeats keep the impairment past its first lease
+        for _ in 0..3 {
+            advance(Duration::from_millis(100)).await;
+            assert_eq!(
+                call(&router, heartbeat.clone()).await,
+                json!({"status": "ok"})
+            );
+        }
+        let show = call(&router, json!({"type": "show", "interface": "wan2"})).await;
+        assert_eq!(show["controls"], json!({"delay": {"time": 100.0}}));
+
+        advance(Duration::from_millis(500)).await;
+        let show = call(&router, json!({"type": "show", "interface": "wan2"})).await;
+        assert_eq!(show["controls"], json!({}));
+        assert_eq!(call(&router, heartbeat).await["status"], "error");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_scenario() {
+        let router = router(Arc::new(Fake::new(&["wan6"])), PathBuf::from("web"));
+        let show = json!({"type": "show", "interface": "wan6"});
+
+        let slow = json!({"delay": {"time": 500.0}});
+        let outage = json!({"loss": {"percent": 100.0, "ecn": false}});
+        let steps =
+            json!([{"controls": slow, "duration": 0.2}, {"controls": outage, "duration": 0.2}]);
+        let start = json!({"type": "start_scenario", "interface": "wan6", "steps": steps});
+        assert_eq!(call(&router, start).await, json!({"status": "ok"}));
+
+        advance(Duration::from_millis(100)).await;
+        assert_eq!(call(&router, show.clone()).await["controls"], slow);
+        let list = call(&router, json!({"type": "scenarios"})).await;
+        assert_eq!(list["list"][0]["interface"], "wan6");
+        assert_eq!(list["list"][0]["step"], 0);
+        assert_eq!(list["list"][0]["steps"], 2);
+
+        advance(Duration::from_millis(200)).await;
+        assert_eq!(call(&router, show.clone()).await["controls"], outage);
+
+        // the interface is reset after the last step
+        advance(Duration::from_millis(300)).await;
+        assert_eq!(call(&router, show.clone()).await["controls"], json!({}));
+        let list = call(&router, json!({"type": "scenarios"})).await;
+        assert_eq!(list["list"], json!([]));
+
+        // a looping scenario runs until it is stopped
+        let start =
+            json!({"type": "start_scenario", "interface": "wan6", "steps": steps, "loop": true});
+        assert_eq!(call(&router, start).await, json!({"status": "ok"}));
+        // a step starts when the task wakes up, at most once per advance
+        for _ in 0..5 {
+            advance(Duration::from_millis(100)).await;
+        }
+        assert_eq!(call(&router, show.clone()).await["controls"], slow);
+        let list = call(&router, json!({"type": "scenarios"})).await;
+        assert_eq!(list["list"][0]["round"], 1);
+
+        let stop = json!({"type": "stop_scenario", "interface": "wan6"});
+        assert_eq!(call(&router, stop.clone()).await, json!({"status": "ok"}));
+        assert_eq!(call(&router, show).await["controls"], json!({}));
+        assert_eq!(call(&router, stop).await["status"], "error");
+
+        let start = json!({"type": "start_scenario", "interface": "wan6", "steps": []});
+        assert_eq!(call(&router, start).await["status"], "error");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_trace() {
+        let router = router(Arc::new(Fake::new(&["wwan0"])), PathBuf::from("web"));
+        let show = json!({"type": "show", "interface": "wwan0"});
+
+        let trace = "0 10000 40 -\n1 2000 120 1\n";
+        let start = json!({"type": "start_trace", "interface": "wwan0", "trace": trace, "speed": 5.0, "controls": {"limit": {"packets": 5000}}});
+        assert_eq!(call(&router, start).await, json!({"status": "ok"}));
+        advance(Duration::from_millis(100)).await;
+        assert_eq!(
+            call(&router, show.clone()).await["controls"],
+            json!({"limit": {"packets": 5000}, "delay": {"time": 40.0}, "rate": {"rate": 10_000_000}})
+        );
+        let list = call(&router, json!({"type": "scenarios"})).await;
+        assert_eq!(list["list"][0]["steps"], 2);
+
+        advance(Duration::from_millis(200)).await;
+        assert_eq!(
+            call(&router, show.clone()).await["controls"]["rate"],
+            json!({"rate": 2_000_000})
+        );
+        advance(Duration::from_millis(300)).await;
+        assert_eq!(call(&router, show).await["controls"], json!({}));
+
+        let start = json!({"type": "start_trace", "interface": "wwan0", "format": "mahimahi", "path": "/nonexistent/trace"});
+        assert_eq!(call(&router, start).await["status"], "error");
+    }
+
+    #[tokio::test]
+    async fn test_profiles() {
+        let router = router(Arc::new(Fake::new(&["wan5"])), PathBuf::from("web"));
+        let show = json!({"type": "show", "interface": "wan5"});
+
+        let lte = call(&router, json!({"type": "show_profile", "name": "lte"})).await;
+        assert_eq!(lte["status"], "profile");
+        assert_eq!(lte["builtin"], true);
+        let set = json!({"type": "set", "interface": "wan5", "profile": "lte"});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        assert_eq!(
+            call(&router, show.clone()).await["controls"],
+            lte["controls"]
+        );
+
+        // bursts of loss need the Gilbert-Elliott model
+        let wifi = call(
+            &router,
+            json!({"type": "show_profile", "name": "lossy-wifi"}),
+        )
+        .await;
+        assert_eq!(
+            wifi["controls"]["loss"],
+            json!({"p": 1.0, "r": 25.0, "1-h": 70.0, "ecn": false})
+        );
+
+        let controls = json!({"delay": {"time": 80.0}});
+        let profile = json!({"type": "set_profile", "name": "office-vpn", "description": "The VPN of the office", "controls": controls});
+        assert_eq!(call(&router, profile).await, json!({"status": "ok"}));
+        let list = call(&router, json!({"type": "profiles"})).await;
+        let list = list["list"].as_array().unwrap();
+        assert!(list
+            .iter()
+            .any(|p| p["name"] == "3g" && p["builtin"] == true));
+        assert_eq!(
+            list.last().unwrap(),
+            &json!({"name": "office-vpn", "description": "The VPN of the office", "controls": controls, "builtin": false})
+        );
+        let set = json!({"type": "set", "interface": "wan5", "profile": "office-vpn"});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        assert_eq!(call(&router, show).await["controls"], controls);
+
+        let remove = json!({"type": "remove_profile", "name": "office-vpn"});
+        assert_eq!(call(&router, remove.clone()).await, json!({"status": "ok"}));
+        assert_eq!(call(&router, remove).await["status"], "error");
+        let set = json!({"type": "set", "interface": "wan5", "profile": "office-vpn"});
+        assert_eq!(call(&router, set).await["status"], "error");
+
+        // built-in profiles can't be changed
+        let remove = json!({"type": "remove_profile", "name": "3g"});
+        assert_eq!(call(&router, remove).await["status"], "error");
+        let profile = json!({"type": "set_profile", "name": "3g", "controls": controls});
+        assert_eq!(call(&router, profile).await["status"], "error");
+
+        let set =
+            json!({"type": "set", "interface": "wan5", "profile": "3g", "controls": controls});
+        assert_eq!(call(&router, set).await["status"], "error");
+        let set = json!({"type": "set", "interface": "wan5"});
+        assert_eq!(call(&router, set).await["status"], "error");
+    }
+
+    #[tokio::test]
+    async fn test_state() -> anyhow::Result<()> {
+        let fake = Arc::new(Fake::new(&["wan4", "lan4"]));
+        let path = std::env::temp_dir().join(format!("taco-state-{}.json", std::process::id()));
+        let delay = json!({"delay": {"time": 100.0}});
+        std::fs::write(
+            &path,
+            json!({"wan4": {"egress": {"controls": delay}}}).to_string(),
+        )?;
+        netem::load_state(fake.as_ref(), path.clone()).await?;
+        let router = router(fake, PathBuf::from("web"));
+
+        // put back at startup
+        let show = call(&router, json!({"type": "show", "interface": "wan4"})).await;
+        assert_eq!(show["controls"], delay);
+
+        let set = json!({"type": "set", "interface": "lan4", "controls": {"loss": {"percent": 2.0, "ecn": false}}});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        let state: Value = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+        assert_eq!(state["wan4"]["egress"]["controls"], delay);
+        assert_eq!(
+            state["lan4"]["egress"]["controls"],
+            json!({"loss": {"percent": 2.0, "ecn": false}})
+        );
+
+        let reset = json!({"type": "reset", "interface": "wan4"});
+        assert_eq!(call(&router, reset).await, json!({"status": "ok"}));
+        let state: Value = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+        assert!(state.get("wan4").is_none());
+        assert!(state.get("lan4").is_some());
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drift() {
+        let fake = Arc::new(Fake::new(&["wan3"]));
+        let router = router(fake.clone(), PathBuf::from("web"));
+        let drift = json!({"type": "drift"});
+        let drifted = |list: Value| {
+            list["list"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|d| d["interface"] == "wan3")
+                .cloned()
+        };
+
+        let controls = json!({"delay": {"time": 70.0}});
+        let set = json!({"type": "set", "interface": "wan3", "controls": controls});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        assert_eq!(drifted(call(&router, drift.clone()).await), None);
+
+        // SQM puts its cake back
+        fake.add_root("wan3", "cake", 0x8002, &["bandwidth", "100Mbit"]);
+        let found = drifted(call(&router, drift.clone()).await).unwrap();
+        assert_eq!(found["desired"]["egress"]["controls"], controls);
+        assert_eq!(found["actual"]["egress"]["controls"], json!({}));
+        assert_eq!(found["checks"], 1);
+        assert_eq!(
+            drifted(call(&router, drift.clone()).await).unwrap()["checks"],
+            2
+        );
+
+        let watch = netem::watch_drift(fake.clone(), Duration::from_millis(50), true);
+        advance(Duration::from_millis(50)).await;
+        watch.abort();
+        let show = call(&router, json!({"type": "show", "interface": "wan3"})).await;
+        assert_eq!(show["controls"], controls);
+        assert_eq!(drifted(call(&router, drift).await), None);
+    }
+
+    #[tokio::test]
+    async fn test_protect() {
+        let fake = Arc::new(Fake::new(&["lan9", "wan9"]));
+        fake.add_client("a4:83:e7:12:34:56", "192.168.1.100", "lan9");
+        netem::protect(fake.as_ref(), vec!["wan9".to_owned()]);
+        let router = router(fake, PathBuf::from("web"));
+
+        let controls = json!({"loss": {"percent": 100.0, "ecn": false}});
+        for interface in ["lan9", "wan9"] {
+            let set = json!({"type": "set", "interface": interface, "controls": controls});
+            assert_eq!(call(&router, set).await["status"], "error");
+            let class = json!({"type": "set_class", "interface": interface, "name": "all", "controls": controls, "match": {}});
+            assert_eq!(call(&router, class).await["status"], "error");
+            let client = json!({"type": "set_client", "interface": interface, "mac": "00:11:22:33:44:55", "controls": controls});
+            assert_eq!(call(&router, client).await["status"], "error");
+        }
+        let show = call(&router, json!({"type": "show", "interface": "lan9"})).await;
+        assert_eq!(show["controls"], json!({}));
+        assert!(show.get("classes").is_none());
+
+        // classes keep the traffic of the caller clean too
+        let class = json!({"type": "set_class", "interface": "lan9", "name": "all", "controls": controls, "match": {}, "force": true});
+        assert_eq!(call(&router, class).await, json!({"status": "ok"}));
+        let show = call(&router, json!({"type": "show", "interface": "lan9"})).await;
+        assert_eq!(show["bypass"], json!([{"dst": "192.168.1.100"}]));
+
+        // the traffic of the caller isn't impaired
+        let set = json!({"type": "set", "interface": "lan9", "controls": controls, "force": true});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        let show = call(&router, json!({"type": "show", "interface": "lan9"})).await;
+        assert_eq!(
+            show["classes"],
+            json!([{"filters": [{}], "controls": controls}])
+        );
+        assert_eq!(show["bypass"], json!([{"dst": "192.168.1.100"}]));
+
+        let set = json!({"type": "set", "interface": "wan9", "controls": controls, "force": true});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        let show = call(&router, json!({"type": "show", "interface": "wan9"})).await;
+        assert_eq!(show["controls"], controls);
+    }
+
+    #[tokio::test]
+    async fn test_original() {
+        let fake = Arc::new(Fake::new(&["wan7", "lan7"]));
+        fake.add_root(
+            "wan7",
+            "cake",
+            0x800a,
+            &["bandwidth", "20Mbit", "diffserv3"],
+        );
+        let router = router(fake.clone(), PathBuf::from("web"));
+
+        let controls = json!({"delay": {"time": 100.0}});
+        let set = json!({"type": "set", "interface": "wan7", "controls": controls});
+        assert_eq!(call(&router, set.clone()).await, json!({"status": "ok"}));
+        assert_eq!(fake.root_kind("wan7").as_deref(), Some("netem"));
+        // a second Set doesn't take taco's netem for the original
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+
+        let reset = json!({"type": "reset", "interface": "wan7"});
+        assert_eq!(call(&router, reset).await, json!({"status": "ok"}));
+        assert_eq!(fake.root_kind("wan7").as_deref(), Some("cake"));
+
+        // so does removing the last class
+        let set = json!({"type": "set_class", "interface": "wan7", "name": "dns", "controls": controls, "match": {"dport": 53}});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        assert_eq!(fake.root_kind("wan7").as_deref(), Some("prio"));
+        let remove = json!({"type": "remove_class", "interface": "wan7", "name": "dns"});
+        assert_eq!(call(&router, remove).await, json!({"status": "ok"}));
+        assert_eq!(fake.root_kind("wan7").as_deref(), Some("cake"));
+
+        // the kernel's default comes back on its own
+        let set = json!({"type": "set", "interface": "lan7", "controls": controls});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        let reset = json!({"type": "reset", "interface": "lan7"});
+        assert_eq!(call(&router, reset).await, json!({"status": "ok"}));
+        assert_eq!(fake.root_kind("lan7"), None);
+    }
+
+    #[tokio::test]
+    async fn test_original_refused() {
+        let fake = Arc::new(Fake::new(&["wan7", "lan7", "ifb4wan7"]));
+        fake.add_root("wan7", "htb", 0x1, &["default", "10"]);
+        fake.add_ingress("lan7", "ifb4wan7");
+        let router = router(fake.clone(), PathBuf::from("web"));
+
+        // taco can't put back the classes and filters of htb
+        let controls = json!({"delay": {"time": 100.0}});
+        for set in [
+            json!({"type": "set", "interface": "wan7", "controls": controls}),
+            json!({"type": "set_class", "interface": "wan7", "name": "dns", "controls": controls, "match": {"dport": 53}}),
+        ] {
+            assert_eq!(call(&router, set).await["status"], "error");
+            assert_eq!(fake.root_kind("wan7").as_deref(), Some("htb"));
+        }
+
+        // nor the ingress qdisc of SQM
+        let set = json!({"type": "set", "interface": "lan7", "controls": controls, "direction": "ingress"});
+        assert_eq!(call(&router, set).await["status"], "error");
+        assert_eq!(fake.ingress("lan7").as_deref(), Some("ifb4wan7"));
+    }
+
+    #[tokio::test]
+    async fn test_original_state() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("taco-originals-{}.json", std::process::id()));
+        let controls = json!({"delay": {"time": 100.0}});
+
+        let fake = Arc::new(Fake::new(&["wan6"]));
+        fake.add_root("wan6", "cake", 0x800b, &["bandwidth", "20Mbit"]);
+        netem::load_state(fake.as_ref(), path.clone()).await?;
+        let before = router(fake, PathBuf::from("web"));
+        let set = json!({"type": "set", "interface": "wan6", "controls": controls});
+        assert_eq!(call(&before, set).await, json!({"status": "ok"}));
+
+        // after a restart, the cake is only in the state
+        let fake = Arc::new(Fake::new(&["wan6"]));
+        fake.add_root("wan6", "netem", 0x8001, &[]);
+        netem::load_state(fake.as_ref(), path.clone()).await?;
+        let router = router(fake.clone(), PathBuf::from("web"));
+        assert_eq!(
+            call(&router, json!({"type": "show", "interface": "wan6"})).await["controls"],
+            controls
+        );
+        let reset = json!({"type": "reset", "interface": "wan6"});
+        assert_eq!(call(&router, reset).await, json!({"status": "ok"}));
+        assert_eq!(fake.root_kind("wan6").as_deref(), Some("cake"));
+
+        std::fs::remove_file(&path)?;
+        std::fs::remove_file(path.with_extension("originals.json"))?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reset_all() {
+        let fake = Arc::new(Fake::new(&["lan8", "wan8", "br8"]));
+        fake.add_client("a4:83:e7:12:34:56", "192.168.1.100", "br8");
+        fake.add_root("br8", "cake", 0x8003, &["bandwidth", "100Mbit"]);
+        let router = router(fake.clone(), PathBuf::from("web"));
+
+        let controls = json!({"delay": {"time": 100.0}});
+        // refused, the API is called through br8
+        let set = json!({"type": "set", "interface": "br8", "controls": controls});
+        assert_eq!(call(&router, set).await["status"], "error");
+        let set =
+            json!({"type": "set", "interface": "lan8", "controls": controls, "direction": "both"});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        let set = json!({"type": "set_class", "interface": "wan8", "name": "web", "controls": controls, "match": {"dport": 443}});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        let steps = json!([{"controls": controls, "duration": 60.0}]);
+        let start = json!({"type": "start_scenario", "interface": "lan8", "steps": steps});
+        assert_eq!(call(&router, start.clone()).await, json!({"status": "ok"}));
+        // the scenarios of other executors keep running
+        let other = super::router(Arc::new(Fake::new(&["lan8"])), PathBuf::from("web"));
+        assert_eq!(call(&other, start).await, json!({"status": "ok"}));
+
+        netem::reset_all(fake.as_ref()).await;
+        let list = call(&router, json!({"type": "scenarios"})).await;
+        assert_eq!(list["list"], json!([]));
+        let list = call(&other, json!({"type": "scenarios"})).await;
+        assert_eq!(list["list"][0]["interface"], "lan8");
+        for interface in ["lan8", "wan8"] {
+            let show = call(&router, json!({"type": "show", "interface": interface})).await;
+            assert_eq!(
+                show,
+                json!({"status": "controls", "interface": interface, "controls": {}})
+            );
+        }
+        assert_eq!(fake.ingress("lan8"), None);
+        // taco never impaired br8
+        assert_eq!(fake.root_kind("br8").as_deref(), Some("cake"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reset_all_ttl() {
+        let fake = Arc::new(Fake::new(&["wan8"]));
+        let router = router(fake.clone(), PathBuf::from("web"));
+
+        let set =
+            json!({"type": "set", "interface": "wan8", "controls": {"delay": {"time": 20.0}}});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+        let set = json!({"type": "set", "interface": "wan8", "controls": {"delay": {"time": 500.0}}, "ttl": 10});
+        assert_eq!(call(&router, set).await, json!({"status": "ok"}));
+
+        netem::reset_all(fake.as_ref()).await;
+        let list = call(&router, json!({"type": "expirations"})).await;
+        assert_eq!(list["list"], json!([]));
+        // the TTL doesn't put its delay back after the reset
+        advance(Duration::from_secs(20)).await;
+        let show = call(&router, json!({"type": "show", "interface": "wan8"})).await;
+        assert_eq!(show["controls"], json!({}));
+    }
 }
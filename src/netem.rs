@@ -7,11 +7,39 @@
 /// interface. NetEm is built using the existing Quality Of Service (QOS)
 /// and Differentiated Services (diffserv) facilities in the Linux
 /// kernel.
+use crate::distribution::{Statistics, Table};
+use clients::Client;
+use expiry::Expiration;
+use filter::{Family, Filter, MacAddr, Match};
+use json::Qdisc;
 use once_cell::sync::Lazy;
 use regex::Regex;
+use scenario::Scenario;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::process::Command;
+use tokio::task::JoinHandle;
+
+mod clients;
+mod drift;
+mod executor;
+mod expiry;
+#[cfg(test)]
+pub mod fake;
+mod filter;
+mod json;
+mod netlink;
+mod profile;
+mod scenario;
+mod state;
+mod trace;
+
+pub use executor::Executor;
 
 type Percentage = f64;
 type Millisecond = f64;
@@ -36,6 +64,22 @@ impl ToMillisecondString for Millisecond {
     }
 }
 
+/// Depending on its version and the value, tc prints a time as `1s`,
+/// `1.5s`, `10.0ms`, `10ms` or `500us`.
+fn parse_millisecond(s: &str) -> anyhow::Result<Millisecond> {
+    let (number, factor) = if let Some(us) = s.strip_suffix("us") {
+        (us, 0.001)
+    } else if let Some(ms) = s.strip_suffix("ms") {
+        (ms, 1.0)
+    } else if let Some(s) = s.strip_suffix('s') {
+        (s, 1000.0)
+    } else {
+        return Err(anyhow::anyhow!("error time: {}", s));
+    };
+
+    Ok(number.parse::<f64>()? * factor)
+}
+
 /// refer to: http://man7.org/linux/man-pages/man8/tc-netem.8.html
 /// tc qdisc ... dev DEVICE ] add netem OPTIONS
 ///
@@ -46,7 +90,7 @@ trait Control {
 }
 
 /// LIMIT := limit packets
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 struct Limit {
     packets: i32,
 }
@@ -79,39 +123,56 @@ impl FromStr for Limit {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
 enum Distribution {
-    #[serde(rename = "uniform")]
     Uniform,
-    #[serde(rename = "normal")]
     Normal,
-    #[serde(rename = "pareto")]
     Pareto,
-    #[serde(rename = "paretonormal")]
     ParetoNormal,
+    /// name of a table file in the tc library directory
+    Custom(String),
 }
 
 impl From<Distribution> for String {
     fn from(distribution: Distribution) -> Self {
         match distribution {
-            Distribution::Uniform => "uniform",
-            Distribution::Normal => "normal",
-            Distribution::Pareto => "pareto",
-            Distribution::ParetoNormal => "paretonormal",
+            Distribution::Uniform => "uniform".to_string(),
+            Distribution::Normal => "normal".to_string(),
+            Distribution::Pareto => "pareto".to_string(),
+            Distribution::ParetoNormal => "paretonormal".to_string(),
+            Distribution::Custom(name) => name,
+        }
+    }
+}
+
+impl TryFrom<String> for Distribution {
+    type Error = anyhow::Error;
+
+    fn try_from(name: String) -> anyhow::Result<Self> {
+        match name.as_str() {
+            "uniform" => Ok(Distribution::Uniform),
+            "normal" => Ok(Distribution::Normal),
+            "pareto" => Ok(Distribution::Pareto),
+            "paretonormal" => Ok(Distribution::ParetoNormal),
+            // joined onto TC_LIB_DIR, it must stay a file name
+            _ if crate::distribution::NAME_REGEX.is_match(&name) => Ok(Distribution::Custom(name)),
+            _ => Err(anyhow::anyhow!("Invalid distribution name: '{}'", name)),
         }
-        .to_string()
     }
 }
 
 /// DELAY := delay TIME [ JITTER [ CORRELATION ]]]
-///        [ distribution { uniform | normal | pareto |  paretonormal } ]
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+///        [ distribution { uniform | normal | pareto |  paretonormal | FILE } ]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 struct Delay {
     time: Millisecond,
     #[serde(skip_serializing_if = "Option::is_none")]
     jitter: Option<Millisecond>,
     #[serde(skip_serializing_if = "Option::is_none")]
     correlation: Option<Percentage>,
+    /// tc never prints it, `show` only knows the ones set by this taco
+    /// process, or put back from its state file
     #[serde(skip_serializing_if = "Option::is_none")]
     distribution: Option<Distribution>,
 }
@@ -130,9 +191,9 @@ impl Control for Delay {
             }
         }
 
-        if let Some(distribution) = self.distribution {
+        if let Some(distribution) = &self.distribution {
             v.push("distribution".into());
-            v.push(distribution.into());
+            v.push(distribution.clone().into());
         }
 
         v
@@ -141,7 +202,7 @@ impl Control for Delay {
 
 static DELAY_REGEX: Lazy<Regex> = Lazy::new(|| {
     Regex::new(
-        r"delay\s(?P<time>[\d\.]+)ms(\s{2}(?P<jitter>[\d\.]+)ms\s((?P<correlation>[\d\.]+)%)?)?",
+        r"delay\s(?P<time>[\d\.]+(us|ms|s))(\s+(?P<jitter>[\d\.]+(us|ms|s))(\s(?P<correlation>[\d\.]+)%)?)?",
     )
     .expect("Failed to create regex of delay")
 });
@@ -151,14 +212,15 @@ impl FromStr for Delay {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if let Some(captures) = DELAY_REGEX.captures(s) {
-            let time: Millisecond = captures
-                .name("time")
-                .ok_or_else(|| anyhow::anyhow!("Failed to get delay time from '{}'", s))?
-                .as_str()
-                .parse()?;
+            let time: Millisecond = parse_millisecond(
+                captures
+                    .name("time")
+                    .ok_or_else(|| anyhow::anyhow!("Failed to get delay time from '{}'", s))?
+                    .as_str(),
+            )?;
 
             let jitter: Option<Millisecond> = match captures.name("jitter") {
-                Some(s) => s.as_str().parse().ok(),
+                Some(s) => parse_millisecond(s.as_str()).ok(),
                 None => None,
             };
 
@@ -171,8 +233,6 @@ impl FromStr for Delay {
                 None
             };
 
-            // TODO: distribution
-
             Ok(Delay {
                 time,
                 jitter,
@@ -188,26 +248,105 @@ impl FromStr for Delay {
 /// LOSS := loss { random PERCENT [ CORRELATION ]  |
 ///                state p13 [ p31 [ p32 [ p23 [ p14]]]] |
 ///                gemodel p [ r [ 1-h [ 1-k ]]] }  [ ecn ]
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
-struct Loss {
-    percent: Percentage,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    correlation: Option<Percentage>,
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+enum Loss {
+    Random {
+        percent: Percentage,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        correlation: Option<Percentage>,
+        #[serde(default)]
+        ecn: bool,
+    },
+    /// 4-state Markov model
+    State {
+        p13: Percentage,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        p31: Option<Percentage>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        p32: Option<Percentage>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        p23: Option<Percentage>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        p14: Option<Percentage>,
+        #[serde(default)]
+        ecn: bool,
+    },
+    /// Gilbert-Elliott model
+    GeModel {
+        p: Percentage,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        r: Option<Percentage>,
+        #[serde(rename = "1-h", skip_serializing_if = "Option::is_none")]
+        one_minus_h: Option<Percentage>,
+        #[serde(rename = "1-k", skip_serializing_if = "Option::is_none")]
+        one_minus_k: Option<Percentage>,
+        #[serde(default)]
+        ecn: bool,
+    },
+}
 
-    // TODO: | state | gemodel
-    #[serde(default)]
-    ecn: bool,
+impl Loss {
+    fn ecn(&self) -> bool {
+        match self {
+            Loss::Random { ecn, .. } | Loss::State { ecn, .. } | Loss::GeModel { ecn, .. } => *ecn,
+        }
+    }
 }
 
 impl Control for Loss {
     fn to_args(&self) -> Vec<String> {
-        let mut v = vec!["loss".into(), "random".into(), self.percent.to_pct_string()];
+        let mut v: Vec<String> = vec!["loss".into()];
+
+        match self {
+            Loss::Random {
+                percent,
+                correlation,
+                ..
+            } => {
+                v.push("random".into());
+                v.push(percent.to_pct_string());
+
+                if let Some(correlation) = correlation {
+                    v.push(correlation.to_pct_string());
+                }
+            }
+            Loss::State {
+                p13,
+                p31,
+                p32,
+                p23,
+                p14,
+                ..
+            } => {
+                v.push("state".into());
+                v.push(p13.to_pct_string());
 
-        if let Some(random) = self.correlation {
-            v.push(random.to_pct_string());
+                // every probability is only meaningful if the previous one is given
+                for p in [p31, p32, p23, p14].into_iter().map_while(|p| p.as_ref()) {
+                    v.push(p.to_pct_string());
+                }
+            }
+            Loss::GeModel {
+                p,
+                r,
+                one_minus_h,
+                one_minus_k,
+                ..
+            } => {
+                v.push("gemodel".into());
+                v.push(p.to_pct_string());
+
+                for p in [r, one_minus_h, one_minus_k]
+                    .into_iter()
+                    .map_while(|p| p.as_ref())
+                {
+                    v.push(p.to_pct_string());
+                }
+            }
         }
 
-        if self.ecn {
+        if self.ecn() {
             v.push("ecn".into());
         }
 
@@ -216,14 +355,34 @@ impl Control for Loss {
 }
 
 static LOSS_REGEX: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(r"loss\s(?P<percent>[\d\.]+)%(\s(?P<correlation>[\d\.]+)%)?(.*\s(?P<ecn>ecn))?")
+    Regex::new(r"loss\s(?P<percent>[\d\.]+)%(\s(?P<correlation>[\d\.]+)%)?")
         .expect("Failed to create regex of loss")
 });
 
+static LOSS_STATE_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"loss\sstate\sp13\s(?P<p13>[\d\.]+)%(\sp31\s(?P<p31>[\d\.]+)%)?(\sp32\s(?P<p32>[\d\.]+)%)?(\sp23\s(?P<p23>[\d\.]+)%)?(\sp14\s(?P<p14>[\d\.]+)%)?",
+    )
+    .expect("Failed to create regex of loss state")
+});
+
+static LOSS_GEMODEL_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"loss\sgemodel\sp\s(?P<p>[\d\.]+)%(\sr\s(?P<r>[\d\.]+)%)?(\s1-h\s(?P<h>[\d\.]+)%)?(\s1-k\s(?P<k>[\d\.]+)%)?",
+    )
+    .expect("Failed to create regex of loss gemodel")
+});
+
+/// `ecn` is printed after all the other options, whatever the loss model is
+static ECN_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\secn(\s|$)").expect("Failed to create regex of ecn"));
+
 impl FromStr for Loss {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ecn = ECN_REGEX.is_match(s);
+
         if let Some(captures) = LOSS_REGEX.captures(s) {
             let percent: Percentage = captures
                 .name("percent")
@@ -236,13 +395,48 @@ impl FromStr for Loss {
                 None => None,
             };
 
-            let ecn = captures.name("ecn").is_some();
-
-            Ok(Loss {
+            Ok(Loss::Random {
                 percent,
                 correlation,
                 ecn,
             })
+        } else if let Some(captures) = LOSS_STATE_REGEX.captures(s) {
+            let p13: Percentage = captures
+                .name("p13")
+                .ok_or_else(|| anyhow::anyhow!("Failed to get loss p13 from '{}'", s))?
+                .as_str()
+                .parse()?;
+
+            let p = |name: &str| -> Option<Percentage> {
+                captures.name(name).and_then(|s| s.as_str().parse().ok())
+            };
+
+            Ok(Loss::State {
+                p13,
+                p31: p("p31"),
+                p32: p("p32"),
+                p23: p("p23"),
+                p14: p("p14"),
+                ecn,
+            })
+        } else if let Some(captures) = LOSS_GEMODEL_REGEX.captures(s) {
+            let p: Percentage = captures
+                .name("p")
+                .ok_or_else(|| anyhow::anyhow!("Failed to get loss gemodel p from '{}'", s))?
+                .as_str()
+                .parse()?;
+
+            let probability = |name: &str| -> Option<Percentage> {
+                captures.name(name).and_then(|s| s.as_str().parse().ok())
+            };
+
+            Ok(Loss::GeModel {
+                p,
+                r: probability("r"),
+                one_minus_h: probability("h"),
+                one_minus_k: probability("k"),
+                ecn,
+            })
         } else {
             Err(anyhow::anyhow!("no loss"))
         }
@@ -250,7 +444,7 @@ impl FromStr for Loss {
 }
 
 /// CORRUPT := corrupt PERCENT [ CORRELATION ]]
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 struct Corrupt {
     percent: Percentage,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -301,7 +495,7 @@ impl FromStr for Corrupt {
 }
 
 /// DUPLICATION := duplicate PERCENT [ CORRELATION ]]
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 struct Duplicate {
     percent: Percentage,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -352,7 +546,7 @@ impl FromStr for Duplicate {
 }
 
 /// REORDERING := reorder PERCENT [ CORRELATION ] [ gap DISTANCE ]
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 struct Reorder {
     percent: Percentage,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -416,20 +610,39 @@ impl FromStr for Reorder {
 }
 
 /// RATE := rate RATE [ PACKETOVERHEAD [ CELLSIZE [ CELLOVERHEAD ]]]]
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 struct Rate {
     rate: u64,
-    // TODO: [ PACKETOVERHEAD [ CELLSIZE [ CELLOVERHEAD ]]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    packetoverhead: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cellsize: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    celloverhead: Option<i32>,
 }
 
 impl Control for Rate {
     fn to_args(&self) -> Vec<String> {
-        vec!["rate".into(), format!("{}bit", self.rate)]
+        let mut v = vec!["rate".into(), format!("{}bit", self.rate)];
+
+        // the options are positional, tc prints them only when they aren't
+        // 0, which is what a missing one defaults to
+        let mut options = vec![
+            self.packetoverhead.map(i64::from),
+            self.cellsize.map(i64::from),
+            self.celloverhead.map(i64::from),
+        ];
+        while options.last() == Some(&None) {
+            options.pop();
+        }
+        v.extend(options.into_iter().map(|o| o.unwrap_or(0).to_string()));
+
+        v
     }
 }
 
 static RATE_REGEX: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(r"rate\s(?P<number>[\d\.]+)(?P<unit>[KMGT]?bit)")
+    Regex::new(r"rate\s(?P<number>[\d\.]+)(?P<unit>[KMGT]?bit)(\spacketoverhead\s(?P<packetoverhead>-?\d+))?(\scellsize\s(?P<cellsize>\d+))?(\scelloverhead\s(?P<celloverhead>-?\d+))?")
         .expect("Failed to create regex of rate")
 });
 
@@ -438,39 +651,207 @@ impl FromStr for Rate {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if let Some(captures) = RATE_REGEX.captures(s) {
-            let number: u64 = captures
+            let number: f64 = captures
                 .name("number")
                 .ok_or_else(|| anyhow::anyhow!("Failed to get rate number from '{}'", s))?
                 .as_str()
                 .parse()?;
 
-            let rate = match captures
+            let multiplier = match captures
                 .name("unit")
                 .ok_or_else(|| anyhow::anyhow!("Faild to get rate unit from '{}'", s))?
                 .as_str()
             {
-                "bit" => Some(number),
-                "Kbit" => number.checked_mul(1_000),
-                "Mbit" => number.checked_mul(1_000_000),
-                "Gbit" => number.checked_mul(1_000_000_000),
-                "Tbit" => number.checked_mul(1_000_000_000_000),
+                "bit" => 1.0,
+                "Kbit" => 1e3,
+                "Mbit" => 1e6,
+                "Gbit" => 1e9,
+                "Tbit" => 1e12,
                 unit => return Err(anyhow::anyhow!("error unit: {}", unit)),
-            }
-            .unwrap_or(u64::MAX);
+            };
+
+            // float to integer casts saturate, so a huge rate ends up as u64::MAX
+            let rate = (number * multiplier).round() as u64;
+
+            let packetoverhead: Option<i32> = match captures.name("packetoverhead") {
+                Some(s) => s.as_str().parse().ok(),
+                None => None,
+            };
+
+            let cellsize: Option<u32> = match captures.name("cellsize") {
+                Some(s) => s.as_str().parse().ok(),
+                None => None,
+            };
+
+            let celloverhead: Option<i32> = match captures.name("celloverhead") {
+                Some(s) => s.as_str().parse().ok(),
+                None => None,
+            };
 
-            Ok(Rate { rate })
+            Ok(Rate {
+                rate,
+                packetoverhead,
+                cellsize,
+                celloverhead,
+            })
         } else {
             Err(anyhow::anyhow!("no rate"))
         }
     }
 }
 
-// TODO: SLOT := slot { MIN_DELAY [ MAX_DELAY ] |
-//                      distribution { uniform | normal | pareto |
-//       paretonormal | FILE } DELAY JITTER }
-//                    [ packets PACKETS ] [ bytes BYTES ]
+/// SLOT := slot { MIN_DELAY [ MAX_DELAY ] |
+///                 distribution { uniform | normal | pareto |
+///  paretonormal | FILE } DELAY JITTER }
+///               [ packets PACKETS ] [ bytes BYTES ]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+enum Slot {
+    Range {
+        min_delay: Millisecond,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        max_delay: Option<Millisecond>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        packets: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        bytes: Option<u32>,
+    },
+    Distribution {
+        /// the kernel doesn't report the table in use, so this is `None` when parsed from tc
+        #[serde(skip_serializing_if = "Option::is_none")]
+        distribution: Option<Distribution>,
+        delay: Millisecond,
+        jitter: Millisecond,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        packets: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        bytes: Option<u32>,
+    },
+}
+
+impl Control for Slot {
+    fn to_args(&self) -> Vec<String> {
+        let mut v: Vec<String> = vec!["slot".into()];
+
+        let (packets, bytes) = match self {
+            Slot::Range {
+                min_delay,
+                max_delay,
+                packets,
+                bytes,
+            } => {
+                v.push(min_delay.to_ms_string());
+                if let Some(max_delay) = max_delay {
+                    v.push(max_delay.to_ms_string());
+                }
+                (packets, bytes)
+            }
+            Slot::Distribution {
+                distribution,
+                delay,
+                jitter,
+                packets,
+                bytes,
+            } => {
+                // tc insists on a table here, normal is the most common one
+                v.push("distribution".into());
+                v.push(distribution.clone().unwrap_or(Distribution::Normal).into());
+                v.push(delay.to_ms_string());
+                v.push(jitter.to_ms_string());
+                (packets, bytes)
+            }
+        };
+
+        if let Some(packets) = packets {
+            v.push("packets".into());
+            v.push(packets.to_string());
+        }
+
+        if let Some(bytes) = bytes {
+            v.push("bytes".into());
+            v.push(bytes.to_string());
+        }
+
+        v
+    }
+}
+
+static SLOT_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"slot\s((distribution\s(?P<delay>[\d\.]+(us|ms|s))\s(?P<jitter>[\d\.]+(us|ms|s)))|((?P<min_delay>[\d\.]+(us|ms|s))(\s(?P<max_delay>[\d\.]+(us|ms|s)))?))(?P<rest>.*)")
+        .expect("Failed to create regex of slot")
+});
+
+/// tc prints the caps of a slot in no particular order
+static SLOT_PACKETS_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\spackets\s(?P<packets>\d+)").expect("Failed to create regex of slot packets")
+});
+
+static SLOT_BYTES_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\sbytes\s(?P<bytes>\d+)").expect("Failed to create regex of slot bytes")
+});
+
+impl FromStr for Slot {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(captures) = SLOT_REGEX.captures(s) {
+            let rest = captures
+                .name("rest")
+                .map(|m| m.as_str())
+                .unwrap_or_default();
+
+            let packets: Option<u32> = match SLOT_PACKETS_REGEX.captures(rest) {
+                Some(c) => c["packets"].parse().ok(),
+                None => None,
+            };
+
+            let bytes: Option<u32> = match SLOT_BYTES_REGEX.captures(rest) {
+                Some(c) => c["bytes"].parse().ok(),
+                None => None,
+            };
+
+            if let Some(min_delay) = captures.name("min_delay") {
+                let max_delay: Option<Millisecond> = match captures.name("max_delay") {
+                    Some(s) => parse_millisecond(s.as_str()).ok(),
+                    None => None,
+                };
+
+                Ok(Slot::Range {
+                    min_delay: parse_millisecond(min_delay.as_str())?,
+                    max_delay,
+                    packets,
+                    bytes,
+                })
+            } else {
+                let delay: Millisecond = parse_millisecond(
+                    captures
+                        .name("delay")
+                        .ok_or_else(|| anyhow::anyhow!("Failed to get slot delay from '{}'", s))?
+                        .as_str(),
+                )?;
+
+                let jitter: Millisecond = parse_millisecond(
+                    captures
+                        .name("jitter")
+                        .ok_or_else(|| anyhow::anyhow!("Failed to get slot jitter from '{}'", s))?
+                        .as_str(),
+                )?;
+
+                Ok(Slot::Distribution {
+                    distribution: None,
+                    delay,
+                    jitter,
+                    packets,
+                    bytes,
+                })
+            }
+        } else {
+            Err(anyhow::anyhow!("no slot"))
+        }
+    }
+}
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
 pub struct Controls {
     #[serde(skip_serializing_if = "Option::is_none")]
     limit: Option<Limit>,
@@ -486,6 +867,8 @@ pub struct Controls {
     reorder: Option<Reorder>,
     #[serde(skip_serializing_if = "Option::is_none")]
     rate: Option<Rate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    slot: Option<Slot>,
 }
 
 impl Control for Controls {
@@ -523,6 +906,10 @@ impl Control for Controls {
             v.append(&mut rate.to_args());
         }
 
+        if let Some(slot) = &self.slot {
+            v.append(&mut slot.to_args());
+        }
+
         v
     }
 }
@@ -542,6 +929,7 @@ impl FromStr for Controls {
         let reorder = Reorder::from_str(s).ok();
         let corrupt = Corrupt::from_str(s).ok();
         let rate = Rate::from_str(s).ok();
+        let slot = Slot::from_str(s).ok();
 
         Ok(Controls {
             limit,
@@ -551,25 +939,410 @@ impl FromStr for Controls {
             duplicate,
             reorder,
             rate,
+            slot,
+        })
+    }
+}
+
+/// Distribution tables applied to an interface.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Distributions {
+    delay: Option<Distribution>,
+    slot: Option<Distribution>,
+}
+
+impl Controls {
+    fn distributions(&self) -> Distributions {
+        Distributions {
+            delay: self.delay.as_ref().and_then(|d| d.distribution.clone()),
+            slot: match &self.slot {
+                Some(Slot::Distribution { distribution, .. }) => distribution.clone(),
+                _ => None,
+            },
+        }
+    }
+
+    /// Fill in the distribution tables the kernel didn't report.
+    fn restore_distributions(&mut self, distributions: &Distributions) {
+        if let Some(delay) = &mut self.delay {
+            if delay.distribution.is_none() && delay.jitter.is_some() {
+                delay.distribution = distributions.delay.clone();
+            }
+        }
+
+        if let Some(Slot::Distribution { distribution, .. }) = &mut self.slot {
+            if distribution.is_none() {
+                *distribution = distributions.slot.clone();
+            }
+        }
+    }
+}
+
+/// Distributions of the netem leaf of a band of taco's prio, device names
+/// can't have a `:`.
+fn leaf_key(device: &str, band: u16) -> String {
+    format!("{}:{:x}", device, band)
+}
+
+/// Keep user-defined profiles in a file, reading the ones already there.
+pub async fn load_profiles(executor: &dyn Executor, path: PathBuf) -> anyhow::Result<()> {
+    profile::load(executor, path).await
+}
+
+/// Keep the impairments interfaces should have in a file, putting back the
+/// ones already there.
+pub async fn load_state(executor: &dyn Executor, path: PathBuf) -> anyhow::Result<()> {
+    state::load(executor, path).await
+}
+
+/// Check the interfaces for drifts every period, putting back the
+/// impairment they should have if `repair`, until the task is aborted.
+pub fn watch_drift(executor: Arc<dyn Executor>, period: Duration, repair: bool) -> JoinHandle<()> {
+    drift::start(executor, period, repair)
+}
+
+/// Refuse to impair interfaces through an executor without `force`.
+pub fn protect(executor: &dyn Executor, interfaces: Vec<String>) {
+    *executor.context().protected.lock().expect("poisoned") = interfaces;
+}
+
+/// Remember that taco impaired an interface, to reset it when it shuts down.
+fn touch(executor: &dyn Executor, interface: &str) {
+    executor
+        .context()
+        .touched
+        .lock()
+        .expect("poisoned")
+        .insert(interface.to_owned());
+}
+
+/// Put back every interface taco impaired the way it was before, when it
+/// shuts down.
+pub async fn reset_all(executor: &dyn Executor) {
+    let interfaces = std::mem::take(&mut *executor.context().touched.lock().expect("poisoned"));
+    for interface in interfaces {
+        // their own put backs would go after the reset
+        scenario::cancel(executor, &interface).await;
+        expiry::cancel(executor, &interface);
+        match NetEm::restore(executor, &interface, &Snapshot::default()).await {
+            Ok(()) => log::info!("Reset {}", interface),
+            Err(e) => log::error!("Failed to reset {}: {}", interface, e),
+        }
+    }
+}
+
+/// What taco keeps about the devices it changes through an executor, which
+/// owns it
+#[derive(Default)]
+pub struct Context {
+    /// interfaces a Set refuses to impair without `force`, besides the one
+    /// the API is called through
+    protected: Mutex<Vec<String>>,
+    /// interfaces taco impaired, to reset them when it shuts down
+    touched: Mutex<BTreeSet<String>>,
+    expirations: expiry::Expirations,
+    scenarios: scenario::Scenarios,
+    drifts: drift::Drifts,
+    profiles: profile::Profiles,
+    /// distribution tables of devices and of the leaves of their bands: the
+    /// kernel never dumps them, so that `show` reports what was actually
+    /// applied. They are lost on restart, unless the impairments are put
+    /// back from the state file.
+    distributions: Mutex<HashMap<String, Distributions>>,
+    /// names of the classes of devices by band, the kernel only knows bands
+    classes: Mutex<HashMap<String, BTreeMap<u16, String>>>,
+    state: state::State,
+    /// root qdiscs of devices before taco first replaced them, like the cake
+    /// of SQM, `None` for the kernel's default one. They are kept next to
+    /// the state file, the root taco leaves behind hides them on restart.
+    originals: Mutex<HashMap<String, Option<Original>>>,
+}
+
+/// The major of the handle of the prio qdisc taco puts at the root of a
+/// device to impair part of its traffic, which tells it apart from others.
+const PRIO_MAJOR: u16 = 0x7ac0;
+/// Bands of taco's prio, with a priomap sending everything to band 1.
+const PRIO_BANDS: u16 = 16;
+/// The band of the traffic no filter matches, never impaired.
+const CLEAN_BAND: u16 = 1;
+/// The band of the traffic matched by a Set, and the first one of classes.
+const MATCH_BAND: u16 = 2;
+
+/// How qdiscs are changed and dumped, chosen at startup.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// spawn the tc binary
+    Tc,
+    /// talk rtnetlink directly, for routers without tc
+    Netlink,
+}
+
+/// Traffic a Set applies to. Ingress traffic is redirected to an IFB device
+/// and impaired on its way out of it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    #[default]
+    Egress,
+    Ingress,
+    Both,
+}
+
+impl Direction {
+    fn both() -> Self {
+        Direction::Both
+    }
+
+    fn egress(self) -> bool {
+        self != Direction::Ingress
+    }
+
+    fn ingress(self) -> bool {
+        self != Direction::Egress
+    }
+}
+
+/// An IFB device taco created for the ingress traffic of an interface.
+///
+/// The interface is kept in the alias of the device, so that taco finds its
+/// devices back after a restart.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Ifb {
+    name: String,
+    interface: String,
+}
+
+const IFB_ALIAS_PREFIX: &str = "taco:";
+
+impl Ifb {
+    fn new(interface: &str) -> Self {
+        // names are at most IFNAMSIZ - 1 bytes long
+        let name = format!("ifb-{}", interface);
+        let name = if name.len() < 16 {
+            name
+        } else {
+            // FNV-1a, stable across restarts
+            let hash = interface.bytes().fold(0x811c9dc5u32, |hash, b| {
+                (hash ^ b as u32).wrapping_mul(0x01000193)
+            });
+            format!("ifb-{:08x}", hash)
+        };
+
+        Ifb {
+            name,
+            interface: interface.to_owned(),
+        }
+    }
+
+    fn alias(&self) -> String {
+        format!("{}{}", IFB_ALIAS_PREFIX, self.interface)
+    }
+
+    /// The IFB device of an interface, if its alias was set by taco.
+    fn from_alias(name: &str, alias: &str) -> Option<Self> {
+        alias.strip_prefix(IFB_ALIAS_PREFIX).map(|interface| Ifb {
+            name: name.to_owned(),
+            interface: interface.to_owned(),
         })
     }
 }
 
+/// Kinds of qdiscs with classes, and the filters sorting traffic into them:
+/// taco only puts back the root qdisc itself, so it leaves them alone.
+const CLASSFUL: &[&str] = &[
+    "atm", "cbq", "drr", "dsmark", "ets", "hfsc", "htb", "mq", "mqprio", "multiq", "prio", "qfq",
+    "taprio",
+];
+
+/// A root qdisc taco replaced, to put it back once it is done.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Original {
+    kind: String,
+    /// major of the handle, never 0
+    handle: u16,
+    options: OriginalOptions,
+}
+
+/// Options of an original qdisc, the way the backend that read them takes
+/// them back
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum OriginalOptions {
+    /// arguments of `tc qdisc replace` after the kind
+    Args(Vec<String>),
+    /// payload of TCA_OPTIONS
+    Netlink(Vec<u8>),
+}
+
+impl Original {
+    /// Whether this is a root taco put there, before it was restarted.
+    fn is_taco(&self) -> bool {
+        self.kind == "netem" || (self.kind == "prio" && self.handle == PRIO_MAJOR)
+    }
+
+    fn has_classes(&self) -> bool {
+        CLASSFUL.contains(&self.kind.as_str())
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
+#[allow(clippy::large_enum_variant)]
 pub enum NetEm {
     #[serde(rename = "set")]
     Set {
         interface: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        controls: Option<Controls>,
+        /// the name of a profile to set, in place of controls
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+        #[serde(default)]
+        direction: Direction,
+        /// only impair the matching traffic, the rest goes through untouched
+        #[serde(rename = "match", default, skip_serializing_if = "Option::is_none")]
+        filter: Option<Match>,
+        /// seconds after which the interface is put back the way it was
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ttl: Option<f64>,
+        /// seconds within which each heartbeat must come, or the interface
+        /// is reset
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        lease: Option<f64>,
+        /// impair a protected interface anyway. The traffic of the caller
+        /// stays clean if the API is called through it.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        force: bool,
+    },
+    /// add a named class impairing the traffic it matches, or update it,
+    /// leaving the other classes alone. Classes replace the netem a Set
+    /// without a match puts at the root.
+    #[serde(rename = "set_class")]
+    SetClass {
+        interface: String,
+        name: String,
         controls: Controls,
+        #[serde(rename = "match")]
+        filter: Match,
+        #[serde(default)]
+        direction: Direction,
+        /// like for a Set
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        force: bool,
+    },
+    #[serde(rename = "remove_class")]
+    RemoveClass {
+        interface: String,
+        name: String,
+        #[serde(default)]
+        direction: Direction,
     },
     #[serde(rename = "show")]
     Show { interface: String },
     // list all names of interfaces
     #[serde(rename = "list")]
     List,
+    /// list the clients of the LAN, from the DHCP leases and the ARP table
+    #[serde(rename = "clients")]
+    Clients,
     #[serde(rename = "reset")]
     Reset { interface: String },
+    /// list the interfaces a Set with a TTL will revert, and when
+    #[serde(rename = "expirations")]
+    Expirations,
+    /// postpone the revert of an interface by some seconds
+    #[serde(rename = "extend")]
+    Extend { interface: String, seconds: f64 },
+    /// keep the impairment of an interface, which won't be reverted
+    #[serde(rename = "cancel")]
+    Cancel { interface: String },
+    /// renew the lease of an interface
+    #[serde(rename = "heartbeat")]
+    Heartbeat { interface: String },
+    /// walk an interface through the steps of a scenario, in the background,
+    /// and reset it after the last one
+    #[serde(rename = "start_scenario")]
+    StartScenario {
+        interface: String,
+        #[serde(flatten)]
+        scenario: Scenario,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        force: bool,
+    },
+    /// replay a trace of rate, delay and loss samples on an interface, as
+    /// its scenario
+    #[serde(rename = "start_trace")]
+    StartTrace {
+        interface: String,
+        #[serde(flatten)]
+        replay: trace::Replay,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        force: bool,
+    },
+    /// list the built-in and user-defined profiles
+    #[serde(rename = "profiles")]
+    Profiles,
+    #[serde(rename = "show_profile")]
+    ShowProfile { name: String },
+    /// add or update a user-defined profile
+    #[serde(rename = "set_profile")]
+    SetProfile {
+        name: String,
+        #[serde(flatten)]
+        profile: profile::Profile,
+    },
+    #[serde(rename = "remove_profile")]
+    RemoveProfile { name: String },
+    /// stop the scenario of an interface and reset it
+    #[serde(rename = "stop_scenario")]
+    StopScenario { interface: String },
+    /// list the running scenarios and their progress
+    #[serde(rename = "scenarios")]
+    Scenarios,
+    /// check for interfaces whose impairment was changed behind taco's back
+    #[serde(rename = "drift")]
+    Drift,
+    /// impair a client of an Ethernet interface, like a phone on `br-lan`,
+    /// by its MAC address: what it receives on egress and what it sends on
+    /// ingress, in classes named after the address
+    #[serde(rename = "set_client")]
+    SetClient {
+        interface: String,
+        mac: MacAddr,
+        controls: Controls,
+        #[serde(default = "Direction::both")]
+        direction: Direction,
+        /// like for a Set
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        force: bool,
+    },
+    /// impair the client calling the API, like `set_client` does, and show
+    /// its controls
+    #[serde(rename = "set_me")]
+    SetMe {
+        controls: Controls,
+        #[serde(default = "Direction::both")]
+        direction: Direction,
+        /// impair the caller on a protected interface anyway, its traffic
+        /// is the one impaired so it isn't kept clean
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        force: bool,
+    },
+    #[serde(rename = "reset_me")]
+    ResetMe,
+    #[serde(rename = "show_client")]
+    ShowClient { interface: String, mac: MacAddr },
+    #[serde(rename = "reset_client")]
+    ResetClient { interface: String, mac: MacAddr },
+    /// build a delay distribution table from measured samples, in the order
+    /// they were measured, and/or histogram buckets of `[value, count]`
+    #[serde(rename = "distribution")]
+    Distribution {
+        name: String,
+        #[serde(default)]
+        samples: Vec<Millisecond>,
+        #[serde(default)]
+        histogram: Vec<(Millisecond, u64)>,
+    },
 }
 
 static INTERFACE_REGEX: Lazy<Regex> = Lazy::new(|| {
@@ -586,115 +1359,1161 @@ fn output_to_interfaces(output: &str) -> Vec<String> {
         .collect::<Vec<String>>()
 }
 
-impl NetEm {
-    async fn do_execute(&self) -> anyhow::Result<Output> {
-        let args = self.to_args();
-        log::info!("Executing => tc {}", args.join(" "));
-        let output = Command::new("tc")
-            .args(args)
-            .output()
-            .await
-            .map_err(|e| anyhow::anyhow!("Command Error: {}", e))?;
-        let output = if let Some(code) = output.status.code() {
-            if code == 0 {
-                let stdout = String::from_utf8(output.stdout)
-                    .map_err(|e| anyhow::anyhow!("Process output decode(utf8) error: {}", e))?;
-                match self {
-                    NetEm::Show { interface } => {
-                        let controls = Controls::from_str(&stdout).map_err(|e| {
-                            anyhow::anyhow!("Parse output to contorls error: {}", e)
-                        })?;
-                        Output::Controls {
-                            interface: interface.into(),
-                            controls,
-                        }
-                    }
-                    NetEm::List => Output::Interfaces {
-                        list: output_to_interfaces(&stdout),
-                    },
-                    _ => Output::Ok,
-                }
-            } else {
-                let description = match String::from_utf8(output.stderr) {
-                    Ok(stderr) => {
-                        format!("Exit with status code: {}, stderr: {}", code, stderr)
-                    }
-                    Err(_) => format!("Exit with status code: {}", code),
-                };
-                Output::err(description)
-            }
+/// Run a command, failing with its stderr if it doesn't exit successfully.
+async fn run(program: &str, args: &[String]) -> anyhow::Result<String> {
+    log::info!("Executing => {} {}", program, args.join(" "));
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| anyhow::anyhow!("Command Error: {}", e))?;
+    if let Some(code) = output.status.code() {
+        if code == 0 {
+            String::from_utf8(output.stdout)
+                .map_err(|e| anyhow::anyhow!("Process output decode(utf8) error: {}", e))
         } else {
-            Output::err("Process killed by signal".to_owned())
-        };
-
-        Ok(output)
-    }
-    pub async fn execute(&self) -> Output {
-        match self.do_execute().await {
-            Ok(output) => output,
-            Err(e) => Output::err(e.to_string()),
+            let description = match String::from_utf8(output.stderr) {
+                Ok(stderr) => {
+                    format!("Exit with status code: {}, stderr: {}", code, stderr)
+                }
+                Err(_) => format!("Exit with status code: {}", code),
+            };
+            Err(anyhow::anyhow!(description))
         }
+    } else {
+        Err(anyhow::anyhow!("Process killed by signal"))
     }
 }
 
-impl Control for NetEm {
-    fn to_args(&self) -> Vec<String> {
+async fn tc(args: &[String]) -> anyhow::Result<String> {
+    run("tc", args).await
+}
+
+async fn ip(args: &[String]) -> anyhow::Result<String> {
+    run("ip", args).await
+}
+
+/// Replace the content of a file by the one `content` makes, without
+/// leaving a truncated file behind. Files are written one at a time, so that
+/// the content made last is the one kept.
+async fn write_file(
+    path: &Path,
+    content: impl FnOnce() -> anyhow::Result<String>,
+) -> anyhow::Result<()> {
+    static WRITING: Lazy<tokio::sync::Mutex<()>> = Lazy::new(|| tokio::sync::Mutex::new(()));
+    let _writing = WRITING.lock().await;
+    let content = content()?;
+
+    let temporary = path.with_extension("tmp");
+    tokio::fs::write(&temporary, content)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", temporary.display(), e))?;
+    tokio::fs::rename(&temporary, path)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Run `tc -j`, which only old tc builds don't support.
+async fn tc_json(args: &[String]) -> anyhow::Result<Vec<Qdisc>> {
+    let mut json_args = vec!["-j".to_owned()];
+    json_args.extend_from_slice(args);
+    json::parse_qdiscs(&tc(&json_args).await?)
+}
+
+/// A change of the impairment of a device, an interface or its IFB device.
+enum Change<'a> {
+    Set {
+        controls: &'a Controls,
+        filter: Option<&'a Match>,
+        /// traffic kept clean
+        bypass: Option<&'a Match>,
+    },
+    /// put back an impairment read from the device
+    Restore(&'a Impairment),
+    SetClass {
+        name: &'a str,
+        controls: &'a Controls,
+        filter: &'a Match,
+        bypass: Option<&'a Match>,
+    },
+}
+
+impl Change<'_> {
+    async fn apply(&self, executor: &dyn Executor, device: &str) -> anyhow::Result<()> {
         match self {
+            Change::Set {
+                controls,
+                filter,
+                bypass,
+            } => NetEm::apply(executor, device, controls, *filter, *bypass).await,
+            Change::SetClass {
+                name,
+                controls,
+                filter,
+                bypass,
+            } => NetEm::set_class(executor, device, name, controls, filter, *bypass).await,
+            Change::Restore(impairment) => NetEm::put_back(executor, device, impairment).await,
+        }
+    }
+}
+
+impl NetEm {
+    async fn do_execute(
+        &self,
+        executor: &Arc<dyn Executor>,
+        caller: Option<IpAddr>,
+    ) -> anyhow::Result<Output> {
+        // expirations outlive the request
+        let (shared, executor) = (executor, executor.as_ref());
+        let output = match self {
             NetEm::Set {
                 interface,
                 controls,
+                profile,
+                direction,
+                filter,
+                ttl,
+                lease,
+                force,
             } => {
-                // tc qdisc replace dev <INTERFACE> root netem delay 100ms 10ms loss 1% 30% duplicate 1% reorder 10% 50% corrupt 0.2%
-                let mut args = vec![
-                    "qdisc".into(),
-                    "replace".into(),
-                    "dev".into(),
-                    interface.into(),
-                    "root".into(),
-                    "netem".into(),
-                ];
-
-                args.append(&mut controls.to_args());
+                let controls = match (controls, profile) {
+                    (Some(controls), None) => controls.clone(),
+                    (None, Some(name)) => profile::get(executor, name)?,
+                    _ => return Err(anyhow::anyhow!("A Set has either controls or a profile")),
+                };
+                let through = NetEm::guard(executor, interface, caller, *force).await?;
 
-                args
-            }
-            NetEm::Show { interface } => {
-                // tc qdisc show dev <INTERFACE>
-                vec![
-                    "qdisc".into(),
-                    "show".into(),
-                    "dev".into(),
-                    interface.into(),
-                ]
+                let seconds = |name, seconds: Option<f64>| {
+                    seconds
+                        .map(Duration::try_from_secs_f64)
+                        .transpose()
+                        .map_err(|e| anyhow::anyhow!("Invalid {}: {}", name, e))
+                };
+                let (ttl, lease) = (seconds("ttl", *ttl)?, seconds("lease", *lease)?);
+                if ttl.is_some() && lease.is_some() {
+                    return Err(anyhow::anyhow!("A Set has either a ttl or a lease"));
+                }
+                let snapshot = match ttl {
+                    Some(_) => Some(NetEm::snapshot(executor, interface).await?),
+                    None => None,
+                };
+
+                // the Set takes over from a scenario
+                scenario::cancel(executor, interface).await;
+                NetEm::set(
+                    executor,
+                    interface,
+                    &controls,
+                    *direction,
+                    filter.as_ref(),
+                    through,
+                )
+                .await?;
+                touch(executor, interface);
+                match (ttl, snapshot, lease) {
+                    (Some(ttl), Some(snapshot), _) => {
+                        expiry::schedule(shared.clone(), interface, snapshot, ttl)
+                    }
+                    (_, _, Some(lease)) => expiry::lease(shared.clone(), interface, lease),
+                    // a Set without a TTL is there to stay
+                    _ => {
+                        expiry::cancel(executor, interface);
+                    }
+                }
+                state::record(executor, interface).await;
+                Output::Ok
+            }
+            NetEm::SetClass {
+                interface,
+                name,
+                controls,
+                filter,
+                direction,
+                force,
+            } => {
+                let through = NetEm::guard(executor, interface, caller, *force).await?;
+                let bypass = (through.map(Match::dst_host), through.map(Match::src_host));
+                let class = |bypass| Change::SetClass {
+                    name,
+                    controls,
+                    filter,
+                    bypass,
+                };
+                let (egress, ingress) = (class(bypass.0.as_ref()), class(bypass.1.as_ref()));
+                NetEm::change(executor, interface, *direction, &egress, &ingress).await?;
+                touch(executor, interface);
+                state::record(executor, interface).await;
+                Output::Ok
+            }
+            NetEm::RemoveClass {
+                interface,
+                name,
+                direction,
+            } => {
+                if direction.egress() {
+                    NetEm::remove_class(executor, interface, name).await?;
+                }
+                if direction.ingress() {
+                    let ifb = NetEm::ifb(executor, interface).await?.ok_or_else(|| {
+                        anyhow::anyhow!("The ingress of {} isn't impaired", interface)
+                    })?;
+                    if NetEm::remove_class(executor, &ifb.name, name).await? {
+                        NetEm::reset_ingress(executor, interface, &ifb).await?;
+                    }
+                }
+                state::record(executor, interface).await;
+                Output::Ok
             }
             NetEm::Reset { interface } => {
-                // tc qdisc del dev <INTERFACE> root netem
-                vec![
-                    "qdisc".into(),
-                    "del".into(),
-                    "dev".into(),
-                    interface.into(),
-                    "root".into(),
-                    "netem".into(),
-                ]
+                NetEm::reset(executor, interface).await?;
+                state::record(executor, interface).await;
+                Output::Ok
+            }
+            NetEm::Expirations => Output::Expirations {
+                list: expiry::list(executor),
+            },
+            NetEm::Extend { interface, seconds } => {
+                let by = Duration::try_from_secs_f64(*seconds)
+                    .map_err(|e| anyhow::anyhow!("Invalid seconds: {}", e))?;
+                expiry::extend(executor, interface, by)?;
+                Output::Ok
+            }
+            NetEm::Heartbeat { interface } => {
+                expiry::heartbeat(executor, interface)?;
+                Output::Ok
+            }
+            NetEm::Cancel { interface } => {
+                if !expiry::cancel(executor, interface) {
+                    return Err(anyhow::anyhow!("{} has no pending expiration", interface));
+                }
+                Output::Ok
+            }
+            NetEm::StartScenario {
+                interface,
+                scenario,
+                force,
+            } => {
+                let through = NetEm::guard(executor, interface, caller, *force).await?;
+                expiry::cancel(executor, interface);
+                scenario::start(shared.clone(), interface, scenario, through).await?;
+                touch(executor, interface);
+                state::forget(executor, interface).await;
+                Output::Ok
+            }
+            NetEm::StartTrace {
+                interface,
+                replay,
+                force,
+            } => {
+                let scenario = replay.scenario().await?;
+                let through = NetEm::guard(executor, interface, caller, *force).await?;
+                expiry::cancel(executor, interface);
+                scenario::start(shared.clone(), interface, &scenario, through).await?;
+                touch(executor, interface);
+                state::forget(executor, interface).await;
+                Output::Ok
+            }
+            NetEm::Profiles => Output::Profiles {
+                list: profile::list(executor),
+            },
+            NetEm::ShowProfile { name } => Output::Profile {
+                profile: profile::find(executor, name)?,
+            },
+            NetEm::SetProfile { name, profile } => {
+                profile::set(executor, name, profile).await?;
+                Output::Ok
+            }
+            NetEm::RemoveProfile { name } => {
+                profile::remove(executor, name).await?;
+                Output::Ok
+            }
+            NetEm::StopScenario { interface } => {
+                if !scenario::cancel(executor, interface).await {
+                    return Err(anyhow::anyhow!("{} runs no scenario", interface));
+                }
+                NetEm::reset(executor, interface).await?;
+                Output::Ok
+            }
+            NetEm::Scenarios => Output::Scenarios {
+                list: scenario::list(executor),
+            },
+            NetEm::Drift => Output::Drift {
+                list: drift::check(executor).await,
+            },
+            NetEm::Show { interface } => {
+                let egress = NetEm::impairment(executor, interface).await?;
+                let ingress = match NetEm::ifb(executor, interface).await? {
+                    Some(ifb) => Some(NetEm::impairment(executor, &ifb.name).await?),
+                    None => None,
+                };
+                Output::Controls {
+                    interface: interface.into(),
+                    egress,
+                    ingress,
+                }
+            }
+            NetEm::SetClient {
+                interface,
+                mac,
+                controls,
+                direction,
+                force,
+            } => {
+                let through = NetEm::guard(executor, interface, caller, *force).await?;
+                NetEm::set_client(executor, interface, *mac, controls, *direction, through).await?;
+                state::record(executor, interface).await;
+                Output::Ok
+            }
+            NetEm::ShowClient { interface, mac } => {
+                NetEm::show_client(executor, interface, *mac).await?
+            }
+            NetEm::ResetClient { interface, mac } => {
+                NetEm::reset_client(executor, interface, *mac).await?;
+                state::record(executor, interface).await;
+                Output::Ok
+            }
+            NetEm::SetMe {
+                controls,
+                direction,
+                force,
+            } => {
+                let (interface, mac) = NetEm::caller(executor, caller).await?;
+                // the API is called through the interface of every caller
+                NetEm::protected(executor, &interface, *force)?;
+                NetEm::set_client(executor, &interface, mac, controls, *direction, None).await?;
+                state::record(executor, &interface).await;
+                NetEm::show_client(executor, &interface, mac).await?
+            }
+            NetEm::ResetMe => {
+                let (interface, mac) = NetEm::caller(executor, caller).await?;
+                NetEm::reset_client(executor, &interface, mac).await?;
+                state::record(executor, &interface).await;
+                Output::Ok
+            }
+            NetEm::List => {
+                let ifbs = executor.ifbs().await?;
+                let list = executor
+                    .list()
+                    .await?
+                    .into_iter()
+                    .filter(|i| !ifbs.iter().any(|ifb| &ifb.name == i))
+                    .collect();
+                Output::Interfaces { list, ifbs }
+            }
+            NetEm::Clients => {
+                let mut clients = executor.clients().await?;
+                let mut impairments = HashMap::new();
+                for client in &mut clients {
+                    let interface = match &client.interface {
+                        Some(interface) => interface,
+                        None => continue,
+                    };
+                    if !impairments.contains_key(interface) {
+                        let mut impairments_of =
+                            vec![NetEm::impairment(executor, interface).await?];
+                        if let Some(ifb) = NetEm::ifb(executor, interface).await? {
+                            impairments_of.push(NetEm::impairment(executor, &ifb.name).await?);
+                        }
+                        impairments.insert(interface.clone(), impairments_of);
+                    }
+                    client.impaired = impairments[interface]
+                        .iter()
+                        .any(|impairment| impairment.impairs(client));
+                }
+                Output::Clients { list: clients }
+            }
+            NetEm::Distribution {
+                name,
+                samples,
+                histogram,
+            } => NetEm::make_distribution(name, samples, histogram).await?,
+        };
+
+        Ok(output)
+    }
+
+    /// Check that an interface may be impaired, returning the address of the
+    /// caller if the API is called through it.
+    async fn guard(
+        executor: &dyn Executor,
+        interface: &str,
+        caller: Option<IpAddr>,
+        force: bool,
+    ) -> anyhow::Result<Option<IpAddr>> {
+        let through = match caller {
+            Some(caller) => match executor.route(caller).await {
+                Ok(device) => (device == interface).then_some(caller),
+                Err(e) => {
+                    log::warn!("Failed to find the route to {}: {}", caller, e);
+                    None
+                }
+            },
+            None => None,
+        };
+        if !force && through.is_some() {
+            return Err(anyhow::anyhow!(
+                "The API is called through {}, set force to impair it anyway",
+                interface
+            ));
+        }
+        NetEm::protected(executor, interface, force)?;
+        Ok(through)
+    }
+
+    /// Check that an interface isn't protected, unless forced.
+    fn protected(executor: &dyn Executor, interface: &str, force: bool) -> anyhow::Result<()> {
+        let protected = executor.context().protected.lock().expect("poisoned");
+        if !force && protected.iter().any(|p| p == interface) {
+            return Err(anyhow::anyhow!(
+                "{} is protected, set force to impair it anyway",
+                interface
+            ));
+        }
+        Ok(())
+    }
+
+    /// Impair the egress and/or the ingress of an interface, keeping the
+    /// traffic of the caller clean if the API is called through it.
+    async fn set(
+        executor: &dyn Executor,
+        interface: &str,
+        controls: &Controls,
+        direction: Direction,
+        filter: Option<&Match>,
+        through: Option<IpAddr>,
+    ) -> anyhow::Result<()> {
+        let bypass = (through.map(Match::dst_host), through.map(Match::src_host));
+        let set = |bypass| Change::Set {
+            controls,
+            filter,
+            bypass,
+        };
+        let (egress, ingress) = (set(bypass.0.as_ref()), set(bypass.1.as_ref()));
+        NetEm::change(executor, interface, direction, &egress, &ingress).await
+    }
+
+    /// Replace the root netem of a device, remembering its distributions.
+    async fn replace(
+        executor: &dyn Executor,
+        device: &str,
+        controls: &Controls,
+    ) -> anyhow::Result<()> {
+        NetEm::save_original(executor, device).await?;
+        executor.replace(device, controls).await?;
+        // classes went away with taco's prio, if there was one
+        NetEm::forget(executor, device);
+        let mut distributions = executor.context().distributions.lock().expect("poisoned");
+        distributions.insert(device.to_owned(), controls.distributions());
+        Ok(())
+    }
+
+    /// Change the egress and/or the ingress of an interface.
+    async fn change(
+        executor: &dyn Executor,
+        interface: &str,
+        direction: Direction,
+        egress: &Change<'_>,
+        ingress: &Change<'_>,
+    ) -> anyhow::Result<()> {
+        if direction.egress() {
+            egress.apply(executor, interface).await?;
+        }
+        if direction.ingress() {
+            NetEm::set_ingress(executor, interface, ingress).await?;
+        }
+        Ok(())
+    }
+
+    /// Impair the traffic of a device, or only the one a match selects with
+    /// a netem leaf of taco's prio, where the bypassed traffic goes to the
+    /// clean band first.
+    async fn apply(
+        executor: &dyn Executor,
+        device: &str,
+        controls: &Controls,
+        filter: Option<&Match>,
+        bypass: Option<&Match>,
+    ) -> anyhow::Result<()> {
+        let filter = match (filter, bypass) {
+            (Some(filter), _) => filter.clone(),
+            (None, Some(_)) => Match::default(),
+            (None, None) => return NetEm::replace(executor, device, controls).await,
+        };
+
+        let filters = filter.compile(MATCH_BAND)?;
+        let bypass = match bypass {
+            Some(bypass) => bypass.compile(CLEAN_BAND)?,
+            None => Vec::new(),
+        };
+        let leaves = executor.leaves(device).await?.unwrap_or_default();
+        NetEm::replace_class(executor, device, MATCH_BAND, controls, &filters).await?;
+        NetEm::replace_filters(executor, device, CLEAN_BAND, &bypass).await?;
+        if let Some(names) = executor
+            .context()
+            .classes
+            .lock()
+            .expect("poisoned")
+            .get_mut(device)
+        {
+            names.remove(&MATCH_BAND);
+        }
+
+        // the match replaces every class
+        for band in leaves.into_keys().filter(|band| *band != MATCH_BAND) {
+            NetEm::delete_band(executor, device, band).await?;
+        }
+        Ok(())
+    }
+
+    /// Add or update a named class of a device, and send the bypassed
+    /// traffic to the clean band if there is some.
+    async fn set_class(
+        executor: &dyn Executor,
+        device: &str,
+        name: &str,
+        controls: &Controls,
+        filter: &Match,
+        bypass: Option<&Match>,
+    ) -> anyhow::Result<()> {
+        let leaves = executor.leaves(device).await?.unwrap_or_default();
+        let band = NetEm::band(executor, device, name)
+            .or_else(|| (MATCH_BAND..=PRIO_BANDS).find(|band| !leaves.contains_key(band)))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{} has {} classes already, the most it can have",
+                    device,
+                    PRIO_BANDS - CLEAN_BAND
+                )
+            })?;
+
+        let filters = filter.compile(band)?;
+        NetEm::replace_class(executor, device, band, controls, &filters).await?;
+        if let Some(bypass) = bypass {
+            let bypass = bypass.compile(CLEAN_BAND)?;
+            NetEm::replace_filters(executor, device, CLEAN_BAND, &bypass).await?;
+        }
+        executor
+            .context()
+            .classes
+            .lock()
+            .expect("poisoned")
+            .entry(device.to_owned())
+            .or_default()
+            .insert(band, name.to_owned());
+        Ok(())
+    }
+
+    /// Remove a named class of a device, and taco's prio with its last one.
+    /// Returns whether the device isn't impaired anymore.
+    async fn remove_class(
+        executor: &dyn Executor,
+        device: &str,
+        name: &str,
+    ) -> anyhow::Result<bool> {
+        let band = NetEm::band(executor, device, name)
+            .ok_or_else(|| anyhow::anyhow!("No class {} on {}", name, device))?;
+        NetEm::delete_band(executor, device, band).await?;
+
+        match executor.leaves(device).await? {
+            Some(leaves) if !leaves.is_empty() => Ok(false),
+            Some(_) => {
+                NetEm::delete_root(executor, device, true).await?;
+                Ok(true)
+            }
+            None => Ok(true),
+        }
+    }
+
+    /// The band of a named class of a device.
+    fn band(executor: &dyn Executor, device: &str, name: &str) -> Option<u16> {
+        let classes = executor.context().classes.lock().expect("poisoned");
+        classes
+            .get(device)?
+            .iter()
+            .find(|(_, n)| n.as_str() == name)
+            .map(|(band, _)| *band)
+    }
+
+    /// Replace the leaf of a band of taco's prio, which is put at the root
+    /// if needed, and the filters sending traffic to it.
+    async fn replace_class(
+        executor: &dyn Executor,
+        device: &str,
+        band: u16,
+        controls: &Controls,
+        filters: &[Filter],
+    ) -> anyhow::Result<()> {
+        NetEm::save_original(executor, device).await?;
+        executor.replace_prio(device).await?;
+        executor.replace_leaf(device, band, controls).await?;
+        executor
+            .context()
+            .distributions
+            .lock()
+            .expect("poisoned")
+            .insert(leaf_key(device, band), controls.distributions());
+        NetEm::replace_filters(executor, device, band, filters).await
+    }
+
+    /// Delete the leaf of a band of taco's prio and its filters.
+    async fn delete_band(executor: &dyn Executor, device: &str, band: u16) -> anyhow::Result<()> {
+        executor
+            .context()
+            .distributions
+            .lock()
+            .expect("poisoned")
+            .remove(&leaf_key(device, band));
+        if let Some(names) = executor
+            .context()
+            .classes
+            .lock()
+            .expect("poisoned")
+            .get_mut(device)
+        {
+            names.remove(&band);
+        }
+
+        NetEm::replace_filters(executor, device, band, &[]).await?;
+        executor.delete_leaf(device, band).await
+    }
+
+    async fn replace_filters(
+        executor: &dyn Executor,
+        device: &str,
+        band: u16,
+        filters: &[Filter],
+    ) -> anyhow::Result<()> {
+        for family in [Family::Ipv4, Family::Ipv6] {
+            let priority = Filter::priority(band, Some(family));
+            if let Err(e) = executor.delete_filters(device, priority).await {
+                log::debug!("No filters of priority {} to replace: {}", priority, e);
+            }
+        }
+        for filter in filters {
+            executor.add_filter(device, filter).await?;
+        }
+        Ok(())
+    }
+
+    /// Remember the root qdisc of a device before taco first replaces it.
+    async fn save_original(executor: &dyn Executor, device: &str) -> anyhow::Result<()> {
+        let originals = &executor.context().originals;
+        if originals.lock().expect("poisoned").contains_key(device) {
+            return Ok(());
+        }
+        // what was there before a root taco left behind is lost, unless it
+        // is in the state
+        let original = executor.root(device).await?.filter(|root| !root.is_taco());
+        if let Some(original) = &original {
+            if original.has_classes() {
+                return Err(anyhow::anyhow!(
+                    "{} has a {} root qdisc, taco can't put back its classes and filters",
+                    device,
+                    original.kind
+                ));
+            }
+            log::info!("Saving the {} root qdisc of {}", original.kind, device);
+        }
+        originals
+            .lock()
+            .expect("poisoned")
+            .insert(device.to_owned(), original);
+        state::save_originals(executor).await;
+        Ok(())
+    }
+
+    /// Delete the root qdisc taco put on a device, its prio or a netem, and
+    /// put back the one it replaced.
+    async fn delete_root(executor: &dyn Executor, device: &str, prio: bool) -> anyhow::Result<()> {
+        if prio {
+            executor.delete_prio(device).await?;
+        } else {
+            executor.delete(device).await?;
+        }
+        NetEm::forget(executor, device);
+
+        let original = executor
+            .context()
+            .originals
+            .lock()
+            .expect("poisoned")
+            .remove(device);
+        state::save_originals(executor).await;
+        match original.flatten() {
+            Some(original) => {
+                log::info!("Restoring the {} root qdisc of {}", original.kind, device);
+                executor.restore_root(device, &original).await
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Forget the distributions of a device and the classes of its leaves.
+    fn forget(executor: &dyn Executor, device: &str) {
+        let leaves = format!("{}:", device);
+        executor
+            .context()
+            .distributions
+            .lock()
+            .expect("poisoned")
+            .retain(|key, _| key != device && !key.starts_with(&leaves));
+        executor
+            .context()
+            .classes
+            .lock()
+            .expect("poisoned")
+            .remove(device);
+    }
+
+    async fn show(executor: &dyn Executor, device: &str) -> anyhow::Result<Controls> {
+        let mut controls = executor.show(device).await?;
+        if let Some(distributions) = executor
+            .context()
+            .distributions
+            .lock()
+            .expect("poisoned")
+            .get(device)
+        {
+            controls.restore_distributions(distributions);
+        }
+        Ok(controls)
+    }
+
+    /// The root netem of a device, or the leaves of taco's prio and the
+    /// traffic their filters match.
+    async fn impairment(executor: &dyn Executor, device: &str) -> anyhow::Result<Impairment> {
+        let leaves = match executor.leaves(device).await? {
+            Some(leaves) => leaves,
+            None => {
+                return Ok(Impairment {
+                    controls: NetEm::show(executor, device).await?,
+                    ..Impairment::default()
+                })
+            }
+        };
+
+        let filters = executor.filters(device).await?;
+        let distributions = executor.context().distributions.lock().expect("poisoned");
+        let names = executor.context().classes.lock().expect("poisoned");
+        let names = names.get(device);
+        let classes = leaves
+            .into_iter()
+            .map(|(band, mut controls)| {
+                if let Some(distributions) = distributions.get(&leaf_key(device, band)) {
+                    controls.restore_distributions(distributions);
+                }
+                Class {
+                    name: names.and_then(|n| n.get(&band)).cloned(),
+                    filters: Match::from_filters(filters.iter().filter(|f| f.band == band)),
+                    controls,
+                }
+            })
+            .collect();
+        Ok(Impairment {
+            controls: Controls::default(),
+            classes,
+            bypass: Match::from_filters(filters.iter().filter(|f| f.band == CLEAN_BAND)),
+        })
+    }
+
+    /// Delete everything taco put on an interface and its IFB device, and
+    /// put back the root qdisc it had before.
+    async fn reset(executor: &dyn Executor, interface: &str) -> anyhow::Result<()> {
+        expiry::cancel(executor, interface);
+        scenario::cancel(executor, interface).await;
+        executor
+            .context()
+            .touched
+            .lock()
+            .expect("poisoned")
+            .remove(interface);
+        let ifb = NetEm::ifb(executor, interface).await?;
+        if let Some(ifb) = &ifb {
+            NetEm::reset_ingress(executor, interface, ifb).await?;
+        }
+
+        let prio = executor.leaves(interface).await?.is_some();
+        match NetEm::delete_root(executor, interface, prio).await {
+            Ok(()) => {}
+            // only ingress was impaired
+            Err(e) if ifb.is_some() => log::debug!("No egress netem to reset: {}", e),
+            Err(e) => return Err(e),
+        }
+        NetEm::forget(executor, interface);
+        Ok(())
+    }
+
+    /// The impairment of both directions of an interface.
+    async fn snapshot(executor: &dyn Executor, interface: &str) -> anyhow::Result<Snapshot> {
+        let egress = NetEm::impairment(executor, interface).await?;
+        let ingress = match NetEm::ifb(executor, interface).await? {
+            Some(ifb) => Some(NetEm::impairment(executor, &ifb.name).await?),
+            None => None,
+        };
+        Ok(Snapshot { egress, ingress })
+    }
+
+    /// Put both directions of an interface back the way they were.
+    async fn restore(
+        executor: &dyn Executor,
+        interface: &str,
+        snapshot: &Snapshot,
+    ) -> anyhow::Result<()> {
+        Change::Restore(&snapshot.egress)
+            .apply(executor, interface)
+            .await?;
+        match (&snapshot.ingress, NetEm::ifb(executor, interface).await?) {
+            (Some(ingress), _) => {
+                NetEm::set_ingress(executor, interface, &Change::Restore(ingress)).await
+            }
+            (None, Some(ifb)) => NetEm::reset_ingress(executor, interface, &ifb).await,
+            (None, None) => Ok(()),
+        }
+    }
+
+    /// Put back an impairment of a device, as `impairment` read it.
+    async fn put_back(
+        executor: &dyn Executor,
+        device: &str,
+        impairment: &Impairment,
+    ) -> anyhow::Result<()> {
+        let leaves = executor.leaves(device).await?;
+        if impairment.classes.is_empty() {
+            if impairment.controls != Controls::default() {
+                return NetEm::replace(executor, device, &impairment.controls).await;
+            }
+            match leaves {
+                Some(_) => NetEm::delete_root(executor, device, true).await?,
+                None if executor.show(device).await? != Controls::default() => {
+                    NetEm::delete_root(executor, device, false).await?
+                }
+                None => NetEm::forget(executor, device),
+            }
+            return Ok(());
+        }
+
+        // classes are in the order of their bands, the class of a Set
+        // first, which don't have to be the same ones again
+        let bands = MATCH_BAND..MATCH_BAND + impairment.classes.len() as u16;
+        if bands.end > PRIO_BANDS + 1 {
+            return Err(anyhow::anyhow!("{} can't have that many classes", device));
+        }
+        for (band, class) in bands.clone().zip(&impairment.classes) {
+            let mut filters = Vec::new();
+            for filter in &class.filters {
+                filters.append(&mut filter.compile(band)?);
+            }
+            NetEm::replace_class(executor, device, band, &class.controls, &filters).await?;
+            let mut names = executor.context().classes.lock().expect("poisoned");
+            let names = names.entry(device.to_owned()).or_default();
+            match &class.name {
+                Some(name) => names.insert(band, name.clone()),
+                None => names.remove(&band),
+            };
+        }
+        for band in leaves.unwrap_or_default().into_keys() {
+            if !bands.contains(&band) {
+                NetEm::delete_band(executor, device, band).await?;
+            }
+        }
+        let mut bypass = Vec::new();
+        for filter in &impairment.bypass {
+            bypass.append(&mut filter.compile(CLEAN_BAND)?);
+        }
+        NetEm::replace_filters(executor, device, CLEAN_BAND, &bypass).await
+    }
+
+    /// The IFB device taco owns for the ingress of an interface.
+    async fn ifb(executor: &dyn Executor, interface: &str) -> anyhow::Result<Option<Ifb>> {
+        Ok(executor
+            .ifbs()
+            .await?
+            .into_iter()
+            .find(|ifb| ifb.interface == interface))
+    }
+
+    /// Impair the ingress of an interface on an IFB device, which is created
+    /// and redirected to on first use.
+    async fn set_ingress(
+        executor: &dyn Executor,
+        interface: &str,
+        change: &Change<'_>,
+    ) -> anyhow::Result<()> {
+        let (ifb, created) = match NetEm::ifb(executor, interface).await? {
+            Some(ifb) => (ifb, false),
+            None => {
+                // taco's would go in place of it, and it can't put it back
+                if executor.has_ingress(interface).await? {
+                    return Err(anyhow::anyhow!(
+                        "{} has an ingress qdisc already, taco can't put it back",
+                        interface
+                    ));
+                }
+                let ifb = Ifb::new(interface);
+                executor.add_ifb(&ifb).await?;
+                (ifb, true)
+            }
+        };
+
+        // redirect again even to an existing device, the ingress qdisc is
+        // gone if the interface was recreated, like wireless ones are
+        let result = match change.apply(executor, &ifb.name).await {
+            Ok(()) => executor.redirect_ingress(interface, &ifb.name).await,
+            result => result,
+        };
+
+        if result.is_err() && created {
+            // don't leave a device behind for an interface that doesn't exist
+            if let Err(e) = executor.delete_ifb(&ifb.name).await {
+                log::warn!("Failed to delete {}: {}", ifb.name, e);
             }
-            NetEm::List => vec!["qdisc".into(), "show".into()],
         }
+
+        result
+    }
+
+    /// Stop redirecting the ingress of an interface and delete its IFB device.
+    async fn reset_ingress(
+        executor: &dyn Executor,
+        interface: &str,
+        ifb: &Ifb,
+    ) -> anyhow::Result<()> {
+        if let Err(e) = executor.delete_ingress(interface).await {
+            log::warn!("Failed to delete the ingress qdisc of {}: {}", interface, e);
+        }
+        executor.delete_ifb(&ifb.name).await?;
+        NetEm::forget(executor, &ifb.name);
+        executor
+            .context()
+            .originals
+            .lock()
+            .expect("poisoned")
+            .remove(&ifb.name);
+        state::save_originals(executor).await;
+        Ok(())
+    }
+
+    async fn make_distribution(
+        name: &str,
+        samples: &[Millisecond],
+        histogram: &[(Millisecond, u64)],
+    ) -> anyhow::Result<Output> {
+        let table = Table::new(samples, histogram)?;
+        table.install(name).await?;
+
+        Ok(Output::Distribution {
+            name: name.to_owned(),
+            statistics: table.statistics,
+        })
+    }
+
+    /// Execute on behalf of the client at `caller`, if it is known, which
+    /// `set_me` and `reset_me` impair.
+    pub async fn execute(&self, executor: &Arc<dyn Executor>, caller: Option<IpAddr>) -> Output {
+        match self.do_execute(executor, caller).await {
+            Ok(output) => output,
+            Err(e) => Output::err(e.to_string()),
+        }
+    }
+
+    /// The interface and the MAC address of a caller, from the ARP table.
+    async fn caller(
+        executor: &dyn Executor,
+        caller: Option<IpAddr>,
+    ) -> anyhow::Result<(String, MacAddr)> {
+        let ip = caller
+            .ok_or_else(|| anyhow::anyhow!("The address of the caller is unknown"))?
+            .to_canonical();
+        executor
+            .clients()
+            .await?
+            .into_iter()
+            .find(|client| client.ip == ip)
+            .and_then(|client| Some((client.interface?, client.mac)))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{} isn't in the ARP table, only clients of the LAN can impair themselves",
+                    ip
+                )
+            })
+    }
+
+    async fn set_client(
+        executor: &dyn Executor,
+        interface: &str,
+        mac: MacAddr,
+        controls: &Controls,
+        direction: Direction,
+        through: Option<IpAddr>,
+    ) -> anyhow::Result<()> {
+        let name = mac.to_string();
+        let bypass = (through.map(Match::dst_host), through.map(Match::src_host));
+        let (to, from) = (Match::dst_mac(mac), Match::src_mac(mac));
+        let class = |filter, bypass| Change::SetClass {
+            name: &name,
+            controls,
+            filter,
+            bypass,
+        };
+        let (egress, ingress) = (
+            class(&to, bypass.0.as_ref()),
+            class(&from, bypass.1.as_ref()),
+        );
+        NetEm::change(executor, interface, direction, &egress, &ingress).await?;
+        touch(executor, interface);
+        Ok(())
+    }
+
+    async fn show_client(
+        executor: &dyn Executor,
+        interface: &str,
+        mac: MacAddr,
+    ) -> anyhow::Result<Output> {
+        let name = mac.to_string();
+        let controls = |impairment: Impairment| {
+            impairment
+                .classes
+                .into_iter()
+                .find(|class| class.name.as_deref() == Some(name.as_str()))
+                .map(|class| class.controls)
+        };
+        let egress = controls(NetEm::impairment(executor, interface).await?);
+        let ingress = match NetEm::ifb(executor, interface).await? {
+            Some(ifb) => controls(NetEm::impairment(executor, &ifb.name).await?),
+            None => None,
+        };
+        Ok(Output::Client {
+            interface: interface.into(),
+            mac,
+            egress,
+            ingress,
+        })
+    }
+
+    async fn reset_client(
+        executor: &dyn Executor,
+        interface: &str,
+        mac: MacAddr,
+    ) -> anyhow::Result<()> {
+        let name = mac.to_string();
+        let mut impaired = false;
+        if NetEm::band(executor, interface, &name).is_some() {
+            NetEm::remove_class(executor, interface, &name).await?;
+            impaired = true;
+        }
+        if let Some(ifb) = NetEm::ifb(executor, interface).await? {
+            if NetEm::band(executor, &ifb.name, &name).is_some() {
+                if NetEm::remove_class(executor, &ifb.name, &name).await? {
+                    NetEm::reset_ingress(executor, interface, &ifb).await?;
+                }
+                impaired = true;
+            }
+        }
+        if !impaired {
+            return Err(anyhow::anyhow!("{} isn't impaired on {}", mac, interface));
+        }
+        Ok(())
+    }
+}
+
+/// The impairment of both directions of an interface, to put it back
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+    egress: Impairment,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ingress: Option<Impairment>,
+}
+
+/// Impaired traffic of a direction of an interface
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Impairment {
+    /// controls of the root netem, default ones when the traffic is
+    /// impaired by classes
+    controls: Controls,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    classes: Vec<Class>,
+    /// traffic no class impairs, whatever their filters match
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    bypass: Vec<Match>,
+}
+
+/// A netem leaf of taco's prio
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Class {
+    /// `None` for the class of a Set, or when taco was restarted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    /// the traffic going through the leaf, as read back from the filters
+    filters: Vec<Match>,
+    controls: Controls,
+}
+
+impl Impairment {
+    /// Whether the whole traffic is impaired, or a class names the client.
+    fn impairs(&self, client: &Client) -> bool {
+        let name = client.mac.to_string();
+        self.controls != Controls::default()
+            || self.classes.iter().any(|class| {
+                class.name.as_deref() == Some(name.as_str())
+                    || class.filters.iter().any(|m| m.names(client.mac, client.ip))
+            })
     }
 }
 
 #[derive(Serialize)]
 #[serde(tag = "status")]
+#[allow(clippy::large_enum_variant)]
 pub enum Output {
     #[serde(rename = "ok")]
     Ok,
     #[serde(rename = "controls")]
     Controls {
         interface: String,
-        controls: Controls,
+        #[serde(flatten)]
+        egress: Impairment,
+        /// impairment of the ingress traffic, if there is one
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ingress: Option<Impairment>,
+    },
+    /// controls of the classes of a client, if it is impaired
+    #[serde(rename = "client")]
+    Client {
+        interface: String,
+        mac: MacAddr,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        egress: Option<Controls>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ingress: Option<Controls>,
+    },
+    #[serde(rename = "clients")]
+    Clients { list: Vec<Client> },
+    #[serde(rename = "expirations")]
+    Expirations { list: Vec<Expiration> },
+    #[serde(rename = "scenarios")]
+    Scenarios { list: Vec<scenario::Status> },
+    #[serde(rename = "drift")]
+    Drift { list: Vec<drift::Drift> },
+    #[serde(rename = "profiles")]
+    Profiles { list: Vec<profile::Named> },
+    #[serde(rename = "profile")]
+    Profile {
+        #[serde(flatten)]
+        profile: profile::Named,
     },
     #[serde(rename = "interfaces")]
-    Interfaces { list: Vec<String> },
+    Interfaces {
+        list: Vec<String>,
+        /// IFB devices taco created for the ingress of interfaces
+        ifbs: Vec<Ifb>,
+    },
+    /// refer to the table with `distribution NAME`, its statistics are the
+    /// delay, jitter and correlation that reproduce the samples
+    #[serde(rename = "distribution")]
+    Distribution {
+        name: String,
+        #[serde(flatten)]
+        statistics: Statistics,
+    },
     #[serde(rename = "error")]
     Error { description: String },
 }
@@ -712,7 +2531,13 @@ mod test {
     fn test_netem() {
         let control = NetEm::Set {
             interface: "br-lan".to_owned(),
-            controls: Controls {
+            direction: Direction::Egress,
+            filter: None,
+            ttl: None,
+            lease: None,
+            force: false,
+            profile: None,
+            controls: Some(Controls {
                 limit: Some(Limit { packets: 2000 }),
                 delay: Some(Delay {
                     time: 10.0,
@@ -720,7 +2545,7 @@ mod test {
                     correlation: Some(50.0),
                     distribution: None,
                 }),
-                loss: Some(Loss {
+                loss: Some(Loss::Random {
                     percent: 0.1,
                     correlation: Some(11.0),
                     ecn: true,
@@ -738,8 +2563,19 @@ mod test {
                     percent: 0.3,
                     correlation: Some(30.0),
                 }),
-                rate: Some(Rate { rate: 10000 }),
-            },
+                rate: Some(Rate {
+                    rate: 10000,
+                    packetoverhead: Some(14),
+                    cellsize: None,
+                    celloverhead: None,
+                }),
+                slot: Some(Slot::Range {
+                    min_delay: 1.0,
+                    max_delay: Some(2.0),
+                    packets: Some(32),
+                    bytes: None,
+                }),
+            }),
         };
 
         assert!(serde_json::to_string(&control).is_ok());
@@ -761,6 +2597,161 @@ mod test {
         assert!(serde_json::to_string(&reset).is_ok())
     }
 
+    #[test]
+    fn test_loss_state() -> anyhow::Result<()> {
+        let loss: Loss = serde_json::from_str(r#"{"p13": 5, "p31": 80, "ecn": true}"#)?;
+
+        assert_eq!(
+            loss.to_args(),
+            vec!["loss", "state", "5.00%", "80.00%", "ecn"]
+        );
+
+        let output = "qdisc netem 8019: root refcnt 2 limit 1000 loss state p13 5% p31 80% p32 0% p23 100% p14 0% ecn ";
+
+        assert_eq!(
+            output.parse::<Loss>()?,
+            Loss::State {
+                p13: 5.0,
+                p31: Some(80.0),
+                p32: Some(0.0),
+                p23: Some(100.0),
+                p14: Some(0.0),
+                ecn: true,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_loss_gemodel() -> anyhow::Result<()> {
+        let loss: Loss = serde_json::from_str(r#"{"p": 1, "r": 30, "1-h": 90}"#)?;
+
+        assert_eq!(
+            loss.to_args(),
+            vec!["loss", "gemodel", "1.00%", "30.00%", "90.00%"]
+        );
+
+        let output = "qdisc netem 801a: root refcnt 2 limit 1000 loss gemodel p 1% r 30% 1-h 90% 1-k 0% rate 1Mbit ecn ";
+
+        assert_eq!(
+            output.parse::<Loss>()?,
+            Loss::GeModel {
+                p: 1.0,
+                r: Some(30.0),
+                one_minus_h: Some(90.0),
+                one_minus_k: Some(0.0),
+                ecn: true,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_slot() -> anyhow::Result<()> {
+        let slot: Slot = serde_json::from_str(
+            r#"{"distribution": "pareto", "delay": 10, "jitter": 2, "bytes": 65536}"#,
+        )?;
+
+        assert_eq!(
+            slot.to_args(),
+            vec![
+                "slot",
+                "distribution",
+                "pareto",
+                "10ms",
+                "2ms",
+                "bytes",
+                "65536"
+            ]
+        );
+
+        let output = "qdisc netem 801b: root refcnt 2 limit 1000 slot 1.0ms 8.0ms packets 42";
+        assert_eq!(
+            output.parse::<Slot>()?,
+            Slot::Range {
+                min_delay: 1.0,
+                max_delay: Some(8.0),
+                packets: Some(42),
+                bytes: None,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rate() -> anyhow::Result<()> {
+        let output = "qdisc netem 801c: root refcnt 2 limit 1000 rate 1.5Mbit packetoverhead 10 cellsize 53 celloverhead -5";
+        let rate = output.parse::<Rate>()?;
+
+        assert_eq!(
+            rate,
+            Rate {
+                rate: 1_500_000,
+                packetoverhead: Some(10),
+                cellsize: Some(53),
+                celloverhead: Some(-5),
+            }
+        );
+        assert_eq!(rate.to_args(), vec!["rate", "1500000bit", "10", "53", "-5"]);
+
+        // tc leaves out the options that are 0, but they stay positional
+        let output = "qdisc netem 801c: root refcnt 2 limit 1000 rate 1Mbit cellsize 48";
+        let rate = output.parse::<Rate>()?;
+        assert_eq!(rate.cellsize, Some(48));
+        assert_eq!(rate.to_args(), vec!["rate", "1000000bit", "0", "48"]);
+        let rate = Rate {
+            celloverhead: Some(-4),
+            ..rate
+        };
+        assert_eq!(rate.to_args(), vec!["rate", "1000000bit", "0", "48", "-4"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_delay_round_trip() -> anyhow::Result<()> {
+        let controls: Controls = serde_json::from_str(
+            r#"{"delay": {"time": 100, "jitter": 10, "correlation": 25, "distribution": "pareto"}}"#,
+        )?;
+
+        assert_eq!(
+            controls.to_args(),
+            vec!["delay", "100ms", "10ms", "25.00%", "distribution", "pareto"]
+        );
+
+        let output = "qdisc netem 801d: root refcnt 2 limit 1000 delay 100ms  10ms 25%";
+        let mut shown = Controls::from_str(output)?;
+        assert_eq!(shown.delay.as_ref().and_then(|d| d.correlation), Some(25.0));
+
+        assert_eq!(
+            shown.delay.as_ref().and_then(|d| d.distribution.clone()),
+            None
+        );
+        shown.restore_distributions(&controls.distributions());
+        assert_eq!(shown.delay, controls.delay);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_distribution_name() {
+        let delay = |distribution: &str| {
+            serde_json::from_value::<Controls>(serde_json::json!({
+                "delay": {"time": 100, "jitter": 10, "distribution": distribution}
+            }))
+        };
+
+        assert_eq!(
+            delay("my-table").unwrap().distributions().delay,
+            Some(Distribution::Custom("my-table".to_string()))
+        );
+        assert!(delay("../x").is_err());
+        assert!(delay("/etc/passwd").is_err());
+    }
+
     #[test]
     fn test_regex() -> anyhow::Result<()> {
         let is_netem = regex::Regex::new(r"^qdisc\snetem\s\d+:.*")?;
@@ -785,6 +2776,7 @@ mod test {
             duplicate: Some(duplicate),
             reorder: Some(reorder),
             rate: Some(rate),
+            slot: None,
         };
 
         assert!(serde_json::to_string(&controls).is_ok());
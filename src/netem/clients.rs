@@ -0,0 +1,131 @@
+/// Clients of the LAN
+///
+/// dnsmasq on OpenWrt writes its DHCP leases to `/tmp/dhcp.leases`, one per
+/// line: `EXPIRY MAC IP HOSTNAME CLIENT-ID`, with `*` for a missing hostname.
+/// Clients with a static address are only in the ARP table.
+use super::filter::MacAddr;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+const LEASES: &str = "/tmp/dhcp.leases";
+const ARP: &str = "/proc/net/arp";
+
+/// ATF_COM, the entry is complete
+const ARP_COMPLETE: u32 = 0x2;
+
+/// A client of the LAN, from the DHCP leases and/or the ARP table.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Client {
+    pub mac: MacAddr,
+    pub ip: IpAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// `None` for leases of clients which aren't in the ARP table
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    /// whether a class of its interface impairs it, filled in by `NetEm`
+    pub impaired: bool,
+}
+
+/// Leases as `(mac, ip, hostname)`, malformed lines are skipped.
+fn parse_leases(leases: &str) -> Vec<(MacAddr, IpAddr, Option<String>)> {
+    leases
+        .lines()
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let mac = fields.get(1)?.parse().ok()?;
+            let ip = fields.get(2)?.parse().ok()?;
+            let hostname = fields.get(3).filter(|h| **h != "*").map(|h| h.to_string());
+            Some((mac, ip, hostname))
+        })
+        .collect()
+}
+
+/// Complete entries of the ARP table as `(mac, ip, device)`.
+fn parse_arp(arp: &str) -> Vec<(MacAddr, IpAddr, String)> {
+    arp.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let flags = u32::from_str_radix(fields.get(2)?.trim_start_matches("0x"), 16).ok()?;
+            if flags & ARP_COMPLETE == 0 {
+                return None;
+            }
+            let ip = fields.first()?.parse().ok()?;
+            let mac = fields.get(3)?.parse().ok()?;
+            Some((mac, ip, fields.get(5)?.to_string()))
+        })
+        .collect()
+}
+
+/// One client per MAC address, where the ARP table has the last word.
+fn merge(leases: &str, arp: &str) -> Vec<Client> {
+    let mut clients = BTreeMap::new();
+    for (mac, ip, hostname) in parse_leases(leases) {
+        clients.insert(
+            mac,
+            Client {
+                mac,
+                ip,
+                hostname,
+                interface: None,
+                impaired: false,
+            },
+        );
+    }
+    for (mac, ip, device) in parse_arp(arp) {
+        let client = clients.entry(mac).or_insert(Client {
+            mac,
+            ip,
+            hostname: None,
+            interface: None,
+            impaired: false,
+        });
+        client.ip = ip;
+        client.interface = Some(device);
+    }
+    clients.into_values().collect()
+}
+
+/// Read the clients of the LAN, the leases file only exists on OpenWrt.
+pub async fn read() -> anyhow::Result<Vec<Client>> {
+    let leases = match tokio::fs::read_to_string(LEASES).await {
+        Ok(leases) => leases,
+        Err(e) => {
+            log::debug!("Failed to read {}: {}", LEASES, e);
+            String::new()
+        }
+    };
+    let arp = tokio::fs::read_to_string(ARP)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", ARP, e))?;
+    Ok(merge(&leases, &arp))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        let leases = "1700000000 a4:83:e7:12:34:56 192.168.1.100 Pixel-7 01:a4:83:e7:12:34:56
+1700000100 3c:22:fb:00:00:01 192.168.1.101 * *
+garbage";
+        let arp = "IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.100    0x1         0x2         a4:83:e7:12:34:56     *        br-lan
+192.168.1.50     0x1         0x2         00:11:22:33:44:55     *        br-lan
+192.168.1.51     0x1         0x0         00:00:00:00:00:00     *        br-lan";
+
+        let clients = merge(leases, arp);
+        assert_eq!(clients.len(), 3);
+        assert_eq!(
+            serde_json::to_value(&clients).unwrap(),
+            serde_json::json!([
+                {"mac": "00:11:22:33:44:55", "ip": "192.168.1.50", "interface": "br-lan", "impaired": false},
+                {"mac": "3c:22:fb:00:00:01", "ip": "192.168.1.101", "impaired": false},
+                {"mac": "a4:83:e7:12:34:56", "ip": "192.168.1.100", "hostname": "Pixel-7", "interface": "br-lan", "impaired": false},
+            ])
+        );
+    }
+}
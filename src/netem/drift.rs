@@ -0,0 +1,133 @@
+/// Drift detection
+///
+/// Other tools, like SQM scripts, hotplug or someone with a shell, may
+/// replace or delete the qdiscs taco installed. A task compares the
+/// impairment every interface has with the one it should have every so
+/// often, and may put the latter back. A drift is only repaired once a
+/// second check finds it, so that a change the API is making in between
+/// isn't undone.
+use super::executor::Executor;
+use super::{state, touch, NetEm, Snapshot};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, MissedTickBehavior};
+
+struct Detected {
+    desired: Snapshot,
+    since: Instant,
+    checks: u32,
+}
+
+/// Drifts found by the last check of the interfaces of an executor
+#[derive(Default)]
+pub struct Drifts {
+    detected: Mutex<HashMap<String, Detected>>,
+}
+
+fn detected(executor: &dyn Executor) -> MutexGuard<'_, HashMap<String, Detected>> {
+    executor.context().drifts.detected.lock().expect("poisoned")
+}
+
+/// An interface whose impairment isn't the one it should have
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Drift {
+    interface: String,
+    desired: Snapshot,
+    /// `None` when it can't be read, like when the interface is gone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    actual: Option<Snapshot>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// seconds since the drift was found
+    seconds: f64,
+    /// checks in a row that found it
+    checks: u32,
+}
+
+/// Check the interfaces every period, repairing their drifts if `repair`,
+/// until the task is aborted.
+pub fn start(executor: Arc<dyn Executor>, period: Duration, repair: bool) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let drifts = check(executor.as_ref()).await;
+            if repair {
+                put_back(executor.as_ref(), drifts).await;
+            }
+        }
+    })
+}
+
+/// Compare the impairment of every interface with the one it should have.
+pub async fn check(executor: &dyn Executor) -> Vec<Drift> {
+    let mut found = Vec::new();
+    for (interface, desired) in state::all(executor) {
+        match NetEm::snapshot(executor, &interface).await {
+            Ok(actual) if actual == desired => {}
+            Ok(actual) => found.push((interface, desired, Some(actual), None)),
+            Err(e) => found.push((interface, desired, None, Some(e.to_string()))),
+        }
+    }
+
+    let now = Instant::now();
+    let mut detected = detected(executor);
+    let mut previous = std::mem::take(&mut *detected);
+    found
+        .into_iter()
+        .map(|(interface, desired, actual, error)| {
+            let drift = match previous.remove(&interface) {
+                Some(drift) if drift.desired == desired => Detected {
+                    checks: drift.checks + 1,
+                    ..drift
+                },
+                _ => {
+                    log::warn!("The impairment of {} drifted", interface);
+                    Detected {
+                        desired: desired.clone(),
+                        since: now,
+                        checks: 1,
+                    }
+                }
+            };
+            let (seconds, checks) = (now.duration_since(drift.since).as_secs_f64(), drift.checks);
+            detected.insert(interface.clone(), drift);
+            Drift {
+                interface,
+                desired,
+                actual,
+                error,
+                seconds,
+                checks,
+            }
+        })
+        .collect()
+}
+
+/// Put back the impairment of the interfaces that drifted, as found by two
+/// checks in a row.
+async fn put_back(executor: &dyn Executor, drifts: Vec<Drift>) {
+    for drift in drifts {
+        if drift.checks < 2
+            || state::desired(executor, &drift.interface).as_ref() != Some(&drift.desired)
+        {
+            continue;
+        }
+        log::info!("Putting back the impairment of {}", drift.interface);
+        match NetEm::restore(executor, &drift.interface, &drift.desired).await {
+            Ok(()) => {
+                touch(executor, &drift.interface);
+                detected(executor).remove(&drift.interface);
+            }
+            Err(e) => log::error!(
+                "Failed to put back the impairment of {}: {}",
+                drift.interface,
+                e
+            ),
+        }
+    }
+}
@@ -0,0 +1,847 @@
+/// Executors of netem operations
+///
+/// `NetEm` only decides what to do, an executor does it: with the tc
+/// binary, over rtnetlink, or in memory for tests.
+use super::clients::{self, Client};
+use super::filter::{self, Filter};
+use super::{ip, netlink, output_to_interfaces, tc, tc_json, Backend, Control, Controls, Ifb};
+use super::{Context, Original, OriginalOptions};
+use super::{CLEAN_BAND, PRIO_BANDS, PRIO_MAJOR};
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[async_trait]
+pub trait Executor: Send + Sync {
+    /// Replace the root qdisc of an interface by a netem one.
+    async fn replace(&self, interface: &str, controls: &Controls) -> anyhow::Result<()>;
+    /// Delete the root netem qdisc of an interface, failing if there is none.
+    async fn delete(&self, interface: &str) -> anyhow::Result<()>;
+    /// Controls of the root netem qdisc of an interface, default ones if
+    /// there is none.
+    async fn show(&self, interface: &str) -> anyhow::Result<Controls>;
+    /// Interfaces with a root qdisc.
+    async fn list(&self) -> anyhow::Result<Vec<String>>;
+    /// The root qdisc of an interface, `None` for the kernel's default one,
+    /// which has no handle.
+    async fn root(&self, interface: &str) -> anyhow::Result<Option<Original>>;
+    /// Replace the root qdisc of an interface by one read with `root`.
+    async fn restore_root(&self, interface: &str, original: &Original) -> anyhow::Result<()>;
+    /// Create an IFB device, mark it as taco's and bring it up.
+    async fn add_ifb(&self, ifb: &Ifb) -> anyhow::Result<()>;
+    async fn delete_ifb(&self, name: &str) -> anyhow::Result<()>;
+    /// IFB devices created by taco.
+    async fn ifbs(&self) -> anyhow::Result<Vec<Ifb>>;
+    /// (Re)create the ingress qdisc of an interface, with a filter
+    /// redirecting all of its traffic to a device.
+    async fn redirect_ingress(&self, interface: &str, device: &str) -> anyhow::Result<()>;
+    async fn delete_ingress(&self, interface: &str) -> anyhow::Result<()>;
+    /// Whether an interface has an ingress qdisc, like the one SQM redirects
+    /// to its own IFB device with.
+    async fn has_ingress(&self, interface: &str) -> anyhow::Result<bool>;
+    /// Replace the root qdisc of an interface by taco's prio, keeping its
+    /// leaves and filters if it already is.
+    async fn replace_prio(&self, interface: &str) -> anyhow::Result<()>;
+    async fn delete_prio(&self, interface: &str) -> anyhow::Result<()>;
+    /// Replace the leaf of a band of taco's prio by a netem qdisc.
+    async fn replace_leaf(
+        &self,
+        interface: &str,
+        band: u16,
+        controls: &Controls,
+    ) -> anyhow::Result<()>;
+    /// Delete the netem leaf of a band of taco's prio.
+    async fn delete_leaf(&self, interface: &str, band: u16) -> anyhow::Result<()>;
+    /// Controls of the netem leaves of taco's prio by band, `None` if the
+    /// root qdisc of the interface isn't taco's prio.
+    async fn leaves(&self, interface: &str) -> anyhow::Result<Option<BTreeMap<u16, Controls>>>;
+    /// Add a filter to taco's prio.
+    async fn add_filter(&self, interface: &str, filter: &Filter) -> anyhow::Result<()>;
+    /// Delete the filters of a priority of taco's prio, failing if there is
+    /// none.
+    async fn delete_filters(&self, interface: &str, priority: u16) -> anyhow::Result<()>;
+    async fn filters(&self, interface: &str) -> anyhow::Result<Vec<Filter>>;
+    /// Clients of the LAN.
+    async fn clients(&self) -> anyhow::Result<Vec<Client>>;
+    /// The interface traffic to an address leaves through.
+    async fn route(&self, address: IpAddr) -> anyhow::Result<String>;
+    fn context(&self) -> &Context;
+}
+
+impl Backend {
+    pub fn executor(self) -> Arc<dyn Executor> {
+        match self {
+            Backend::Tc => Arc::new(Tc::default()),
+            Backend::Netlink => Arc::new(Netlink::default()),
+        }
+    }
+}
+
+/// A link as printed by `ip -j link show`
+#[derive(Deserialize)]
+struct Link {
+    ifname: String,
+    ifalias: Option<String>,
+}
+
+static LINK_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^\d+:\s(?P<name>[^:@\s]+)[:@]").expect("Failed to create regex of link")
+});
+
+static ALIAS_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^\s+alias\s(?P<alias>.+)$").expect("Failed to create regex of alias")
+});
+
+/// `(name, alias)` of the links in the text output of `ip link show`
+fn output_to_aliases(output: &str) -> Vec<(String, String)> {
+    let mut aliases = Vec::new();
+    let mut name = None;
+    for line in output.lines() {
+        if let Some(captures) = LINK_REGEX.captures(line) {
+            name = Some(captures["name"].to_owned());
+        } else if let (Some(name), Some(captures)) = (&name, ALIAS_REGEX.captures(line)) {
+            aliases.push((name.clone(), captures["alias"].to_owned()));
+        }
+    }
+    aliases
+}
+
+/// A route as printed by `ip -j route get`
+#[derive(Deserialize)]
+struct Route {
+    dev: String,
+}
+
+static ROUTE_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\sdev\s(?P<dev>\S+)").expect("Failed to create regex of route"));
+
+/// `7ac0:2`
+fn class_id(band: u16) -> String {
+    format!("{:x}:{:x}", PRIO_MAJOR, band)
+}
+
+/// `7ac2:`, leaves need handles of their own
+fn leaf_handle(band: u16) -> String {
+    format!("{:x}:", PRIO_MAJOR + band)
+}
+
+/// `7ac0:2` to `(0x7ac0, 2)`
+fn parse_class_id(s: &str) -> Option<(u16, u16)> {
+    let (major, minor) = s.split_once(':')?;
+    let major = u16::from_str_radix(major, 16).ok()?;
+    let minor = if minor.is_empty() {
+        0
+    } else {
+        u16::from_str_radix(minor, 16).ok()?
+    };
+    Some((major, minor))
+}
+
+/// The leaves of taco's prio among the `(kind, handle, parent, options)`
+/// of the qdiscs of a device, a root qdisc having no parent.
+fn prio_leaves<T>(qdiscs: Vec<(String, String, Option<String>, T)>) -> Option<BTreeMap<u16, T>> {
+    let prio = (PRIO_MAJOR, 0);
+    if !qdiscs.iter().any(|(kind, handle, parent, _)| {
+        kind == "prio" && parent.is_none() && parse_class_id(handle) == Some(prio)
+    }) {
+        return None;
+    }
+
+    Some(
+        qdiscs
+            .into_iter()
+            .filter(|(kind, ..)| kind == "netem")
+            .filter_map(|(_, _, parent, options)| match parse_class_id(&parent?)? {
+                (PRIO_MAJOR, band) => Some((band, options)),
+                _ => None,
+            })
+            .collect(),
+    )
+}
+
+static QDISC_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^qdisc\s(?P<kind>\S+)\s(?P<handle>[0-9a-f]+:)\s(dev\s\S+\s)?(root|parent\s(?P<parent>[0-9a-f]+:[0-9a-f]*))")
+        .expect("Failed to create regex of qdisc")
+});
+
+/// `(kind, handle, parent, line)` of the qdiscs in the text output of `tc
+/// qdisc show`
+fn output_to_qdiscs(output: &str) -> Vec<(String, String, Option<String>, String)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let captures = QDISC_REGEX.captures(line)?;
+            Some((
+                captures["kind"].to_owned(),
+                captures["handle"].to_owned(),
+                captures.name("parent").map(|m| m.as_str().to_owned()),
+                line.to_owned(),
+            ))
+        })
+        .collect()
+}
+
+static ROOT_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^qdisc\s(?P<kind>\S+)\s(?P<handle>[0-9a-f]+):\s(dev\s\S+\s)?root(\srefcnt\s\d+)?(?P<options>.*)$")
+        .expect("Failed to create regex of root")
+});
+
+/// The root qdisc in the text output of `tc qdisc show dev`, with its options
+/// as arguments of `tc qdisc replace`.
+fn output_to_root(output: &str) -> Option<Original> {
+    let captures = output.lines().find_map(|line| ROOT_REGEX.captures(line))?;
+    let handle = u16::from_str_radix(&captures["handle"], 16).ok()?;
+    if handle == 0 {
+        return None;
+    }
+
+    // tc prints limits in packets like `10240p`, but only takes numbers back
+    let args = captures["options"]
+        .split_whitespace()
+        .map(|arg| match arg.strip_suffix('p') {
+            Some(packets) if packets.bytes().all(|b| b.is_ascii_digit()) => packets.to_owned(),
+            _ => arg.to_owned(),
+        })
+        .collect();
+    Some(Original {
+        kind: captures["kind"].to_owned(),
+        handle,
+        options: OriginalOptions::Args(args),
+    })
+}
+
+/// Spawns tc, and ip for links
+#[derive(Default)]
+pub struct Tc {
+    context: Context,
+}
+
+#[async_trait]
+impl Executor for Tc {
+    async fn replace(&self, interface: &str, controls: &Controls) -> anyhow::Result<()> {
+        // tc qdisc replace dev <INTERFACE> root netem delay 100ms 10ms loss 1% 30% duplicate 1% reorder 10% 50% corrupt 0.2%
+        let mut args = vec![
+            "qdisc".into(),
+            "replace".into(),
+            "dev".into(),
+            interface.into(),
+            "root".into(),
+            "netem".into(),
+        ];
+        args.append(&mut controls.to_args());
+
+        tc(&args).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, interface: &str) -> anyhow::Result<()> {
+        // tc qdisc del dev <INTERFACE> root netem
+        tc(&[
+            "qdisc".into(),
+            "del".into(),
+            "dev".into(),
+            interface.into(),
+            "root".into(),
+            "netem".into(),
+        ])
+        .await?;
+        Ok(())
+    }
+
+    async fn show(&self, interface: &str) -> anyhow::Result<Controls> {
+        // tc qdisc show dev <INTERFACE>
+        let args = vec![
+            "qdisc".into(),
+            "show".into(),
+            "dev".into(),
+            interface.into(),
+        ];
+
+        let json = tc_json(&args).await.and_then(|qdiscs| {
+            match qdiscs.iter().find(|q| q.kind == "netem" && q.root) {
+                Some(netem) => Controls::from_json(&netem.options),
+                None => Ok(Controls::default()),
+            }
+        });
+        match json {
+            Ok(controls) => Ok(controls),
+            Err(e) => {
+                log::debug!("Falling back to tc text output: {}", e);
+                Controls::from_str(&tc(&args).await?)
+                    .map_err(|e| anyhow::anyhow!("Parse output to contorls error: {}", e))
+            }
+        }
+    }
+
+    async fn list(&self) -> anyhow::Result<Vec<String>> {
+        let args = vec!["qdisc".into(), "show".into()];
+
+        match tc_json(&args).await {
+            Ok(qdiscs) => Ok(qdiscs
+                .into_iter()
+                .filter(|q| q.root)
+                .filter_map(|q| q.dev)
+                .collect()),
+            Err(e) => {
+                log::debug!("Falling back to tc text output: {}", e);
+                Ok(output_to_interfaces(&tc(&args).await?))
+            }
+        }
+    }
+
+    async fn root(&self, interface: &str) -> anyhow::Result<Option<Original>> {
+        // tc qdisc show dev <INTERFACE> root
+        let output = tc(&[
+            "qdisc".into(),
+            "show".into(),
+            "dev".into(),
+            interface.into(),
+            "root".into(),
+        ])
+        .await?;
+        Ok(output_to_root(&output))
+    }
+
+    async fn restore_root(&self, interface: &str, original: &Original) -> anyhow::Result<()> {
+        let options = match &original.options {
+            OriginalOptions::Args(args) => args,
+            OriginalOptions::Netlink(_) => {
+                return Err(anyhow::anyhow!(
+                    "The options of {} aren't tc's",
+                    original.kind
+                ))
+            }
+        };
+        // tc qdisc replace dev <INTERFACE> root handle <HANDLE>: <KIND> <OPTIONS>
+        let mut args = vec![
+            "qdisc".into(),
+            "replace".into(),
+            "dev".into(),
+            interface.into(),
+            "root".into(),
+            "handle".into(),
+            format!("{:x}:", original.handle),
+            original.kind.clone(),
+        ];
+        args.extend_from_slice(options);
+
+        tc(&args).await?;
+        Ok(())
+    }
+
+    async fn add_ifb(&self, ifb: &Ifb) -> anyhow::Result<()> {
+        // ip link add name <IFB> type ifb
+        ip(&[
+            "link".into(),
+            "add".into(),
+            "name".into(),
+            ifb.name.clone(),
+            "type".into(),
+            "ifb".into(),
+        ])
+        .await?;
+        // ip link set dev <IFB> alias taco:<INTERFACE> up
+        ip(&[
+            "link".into(),
+            "set".into(),
+            "dev".into(),
+            ifb.name.clone(),
+            "alias".into(),
+            ifb.alias(),
+            "up".into(),
+        ])
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_ifb(&self, name: &str) -> anyhow::Result<()> {
+        // ip link del dev <IFB>
+        ip(&["link".into(), "del".into(), "dev".into(), name.into()]).await?;
+        Ok(())
+    }
+
+    async fn ifbs(&self) -> anyhow::Result<Vec<Ifb>> {
+        let args: Vec<String> = vec!["link".into(), "show".into(), "type".into(), "ifb".into()];
+
+        let mut json_args = vec!["-j".to_owned()];
+        json_args.extend_from_slice(&args);
+        let json = ip(&json_args).await.and_then(|output| {
+            serde_json::from_str::<Vec<Link>>(&output)
+                .map_err(|e| anyhow::anyhow!("Invalid ip JSON output: {}", e))
+        });
+        let aliases = match json {
+            Ok(links) => links
+                .into_iter()
+                .filter_map(|l| l.ifalias.map(|alias| (l.ifname, alias)))
+                .collect(),
+            Err(e) => {
+                log::debug!("Falling back to ip text output: {}", e);
+                output_to_aliases(&ip(&args).await?)
+            }
+        };
+
+        Ok(aliases
+            .iter()
+            .filter_map(|(name, alias)| Ifb::from_alias(name, alias))
+            .collect())
+    }
+
+    async fn redirect_ingress(&self, interface: &str, device: &str) -> anyhow::Result<()> {
+        if let Err(e) = self.delete_ingress(interface).await {
+            log::debug!("No ingress qdisc to replace: {}", e);
+        }
+        // tc qdisc add dev <INTERFACE> handle ffff: ingress
+        tc(&[
+            "qdisc".into(),
+            "add".into(),
+            "dev".into(),
+            interface.into(),
+            "handle".into(),
+            "ffff:".into(),
+            "ingress".into(),
+        ])
+        .await?;
+        // tc filter add dev <INTERFACE> parent ffff: protocol all prio 1 u32 match u32 0 0 action mirred egress redirect dev <DEVICE>
+        let filter = format!(
+            "filter add dev {} parent ffff: protocol all prio 1 u32 match u32 0 0 action mirred egress redirect dev {}",
+            interface, device
+        );
+        tc(&filter.split(' ').map(String::from).collect::<Vec<String>>()).await?;
+        Ok(())
+    }
+
+    async fn delete_ingress(&self, interface: &str) -> anyhow::Result<()> {
+        // tc qdisc del dev <INTERFACE> ingress
+        tc(&[
+            "qdisc".into(),
+            "del".into(),
+            "dev".into(),
+            interface.into(),
+            "ingress".into(),
+        ])
+        .await?;
+        Ok(())
+    }
+
+    async fn has_ingress(&self, interface: &str) -> anyhow::Result<bool> {
+        // tc qdisc show dev <INTERFACE> ingress
+        let output = tc(&[
+            "qdisc".into(),
+            "show".into(),
+            "dev".into(),
+            interface.into(),
+            "ingress".into(),
+        ])
+        .await?;
+        Ok(output.lines().any(|line| line.starts_with("qdisc ")))
+    }
+
+    async fn replace_prio(&self, interface: &str) -> anyhow::Result<()> {
+        // tc qdisc replace dev <INTERFACE> root handle 7ac0: prio bands 16 priomap 0 0 ... 0
+        let mut args = vec![
+            "qdisc".into(),
+            "replace".into(),
+            "dev".into(),
+            interface.into(),
+            "root".into(),
+            "handle".into(),
+            format!("{:x}:", PRIO_MAJOR),
+            "prio".into(),
+            "bands".into(),
+            PRIO_BANDS.to_string(),
+            "priomap".into(),
+        ];
+        args.extend((0..16).map(|_| "0".to_owned()));
+        tc(&args).await?;
+
+        // the default pfifo of a band is as long as the transmit queue,
+        // which is 32 packets for IFB devices
+        // tc qdisc replace dev <INTERFACE> parent 7ac0:1 handle 7ac1: pfifo limit 1000
+        tc(&[
+            "qdisc".into(),
+            "replace".into(),
+            "dev".into(),
+            interface.into(),
+            "parent".into(),
+            class_id(CLEAN_BAND),
+            "handle".into(),
+            leaf_handle(CLEAN_BAND),
+            "pfifo".into(),
+            "limit".into(),
+            "1000".into(),
+        ])
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_prio(&self, interface: &str) -> anyhow::Result<()> {
+        // tc qdisc del dev <INTERFACE> root handle 7ac0: prio
+        tc(&[
+            "qdisc".into(),
+            "del".into(),
+            "dev".into(),
+            interface.into(),
+            "root".into(),
+            "handle".into(),
+            format!("{:x}:", PRIO_MAJOR),
+            "prio".into(),
+        ])
+        .await?;
+        Ok(())
+    }
+
+    async fn replace_leaf(
+        &self,
+        interface: &str,
+        band: u16,
+        controls: &Controls,
+    ) -> anyhow::Result<()> {
+        // tc qdisc replace dev <INTERFACE> parent 7ac0:<BAND> handle <7ac0 + BAND>: netem ...
+        let mut args = vec![
+            "qdisc".into(),
+            "replace".into(),
+            "dev".into(),
+            interface.into(),
+            "parent".into(),
+            class_id(band),
+            "handle".into(),
+            leaf_handle(band),
+            "netem".into(),
+        ];
+        args.append(&mut controls.to_args());
+
+        tc(&args).await?;
+        Ok(())
+    }
+
+    async fn delete_leaf(&self, interface: &str, band: u16) -> anyhow::Result<()> {
+        // tc qdisc del dev <INTERFACE> parent 7ac0:<BAND> handle <7ac0 + BAND>: netem
+        tc(&[
+            "qdisc".into(),
+            "del".into(),
+            "dev".into(),
+            interface.into(),
+            "parent".into(),
+            class_id(band),
+            "handle".into(),
+            leaf_handle(band),
+            "netem".into(),
+        ])
+        .await?;
+        Ok(())
+    }
+
+    async fn leaves(&self, interface: &str) -> anyhow::Result<Option<BTreeMap<u16, Controls>>> {
+        let args = vec![
+            "qdisc".into(),
+            "show".into(),
+            "dev".into(),
+            interface.into(),
+        ];
+
+        let json = tc_json(&args).await.and_then(|qdiscs| {
+            let qdiscs = qdiscs
+                .into_iter()
+                .map(|q| {
+                    let parent = if q.root { None } else { q.parent };
+                    (q.kind, q.handle.unwrap_or_default(), parent, q.options)
+                })
+                .collect();
+            prio_leaves(qdiscs)
+                .map(|leaves| {
+                    leaves
+                        .into_iter()
+                        .map(|(band, options)| Ok((band, Controls::from_json(&options)?)))
+                        .collect()
+                })
+                .transpose()
+        });
+        match json {
+            Ok(leaves) => Ok(leaves),
+            Err(e) => {
+                log::debug!("Falling back to tc text output: {}", e);
+                prio_leaves(output_to_qdiscs(&tc(&args).await?))
+                    .map(|leaves| {
+                        leaves
+                            .into_iter()
+                            .map(|(band, line)| Ok((band, Controls::from_str(&line)?)))
+                            .collect()
+                    })
+                    .transpose()
+            }
+        }
+    }
+
+    async fn add_filter(&self, interface: &str, filter: &Filter) -> anyhow::Result<()> {
+        // tc filter add dev <INTERFACE> parent 7ac0: protocol ip prio 4 u32 match u32 0x0a000100 0xffffff00 at 16 flowid 7ac0:2
+        let mut args = vec![
+            "filter".into(),
+            "add".into(),
+            "dev".into(),
+            interface.into(),
+            "parent".into(),
+            format!("{:x}:", PRIO_MAJOR),
+        ];
+        args.append(&mut filter.to_args());
+
+        tc(&args).await?;
+        Ok(())
+    }
+
+    async fn delete_filters(&self, interface: &str, priority: u16) -> anyhow::Result<()> {
+        // tc filter del dev <INTERFACE> parent 7ac0: prio <PRIORITY>
+        tc(&[
+            "filter".into(),
+            "del".into(),
+            "dev".into(),
+            interface.into(),
+            "parent".into(),
+            format!("{:x}:", PRIO_MAJOR),
+            "prio".into(),
+            priority.to_string(),
+        ])
+        .await?;
+        Ok(())
+    }
+
+    async fn filters(&self, interface: &str) -> anyhow::Result<Vec<Filter>> {
+        // tc filter show dev <INTERFACE> parent 7ac0:
+        let output = tc(&[
+            "filter".into(),
+            "show".into(),
+            "dev".into(),
+            interface.into(),
+            "parent".into(),
+            format!("{:x}:", PRIO_MAJOR),
+        ])
+        .await?;
+        Ok(filter::output_to_filters(&output))
+    }
+
+    async fn clients(&self) -> anyhow::Result<Vec<Client>> {
+        clients::read().await
+    }
+
+    async fn route(&self, address: IpAddr) -> anyhow::Result<String> {
+        let args: Vec<String> = vec!["route".into(), "get".into(), address.to_string()];
+
+        let mut json_args = vec!["-j".to_owned()];
+        json_args.extend_from_slice(&args);
+        let json = ip(&json_args).await.and_then(|output| {
+            serde_json::from_str::<Vec<Route>>(&output)
+                .map_err(|e| anyhow::anyhow!("Invalid ip JSON output: {}", e))
+        });
+        let dev = match json {
+            Ok(routes) => routes.into_iter().next().map(|r| r.dev),
+            Err(e) => {
+                log::debug!("Falling back to ip text output: {}", e);
+                ROUTE_REGEX
+                    .captures(&ip(&args).await?)
+                    .map(|captures| captures["dev"].to_owned())
+            }
+        };
+        dev.ok_or_else(|| anyhow::anyhow!("No route to {}", address))
+    }
+
+    fn context(&self) -> &Context {
+        &self.context
+    }
+}
+
+/// Talks rtnetlink
+#[derive(Default)]
+pub struct Netlink {
+    context: Context,
+}
+
+#[async_trait]
+impl Executor for Netlink {
+    async fn replace(&self, interface: &str, controls: &Controls) -> anyhow::Result<()> {
+        netlink::replace(interface, controls).await
+    }
+
+    async fn delete(&self, interface: &str) -> anyhow::Result<()> {
+        netlink::delete(interface).await
+    }
+
+    async fn show(&self, interface: &str) -> anyhow::Result<Controls> {
+        netlink::show(interface).await
+    }
+
+    async fn list(&self) -> anyhow::Result<Vec<String>> {
+        netlink::list().await
+    }
+
+    async fn root(&self, interface: &str) -> anyhow::Result<Option<Original>> {
+        netlink::root(interface).await
+    }
+
+    async fn restore_root(&self, interface: &str, original: &Original) -> anyhow::Result<()> {
+        netlink::restore_root(interface, original).await
+    }
+
+    async fn add_ifb(&self, ifb: &Ifb) -> anyhow::Result<()> {
+        netlink::add_ifb(ifb).await
+    }
+
+    async fn delete_ifb(&self, name: &str) -> anyhow::Result<()> {
+        netlink::delete_link(name).await
+    }
+
+    async fn ifbs(&self) -> anyhow::Result<Vec<Ifb>> {
+        netlink::ifbs().await
+    }
+
+    async fn redirect_ingress(&self, interface: &str, device: &str) -> anyhow::Result<()> {
+        netlink::redirect_ingress(interface, device).await
+    }
+
+    async fn delete_ingress(&self, interface: &str) -> anyhow::Result<()> {
+        netlink::delete_ingress(interface).await
+    }
+
+    async fn has_ingress(&self, interface: &str) -> anyhow::Result<bool> {
+        netlink::has_ingress(interface).await
+    }
+
+    async fn replace_prio(&self, interface: &str) -> anyhow::Result<()> {
+        netlink::replace_prio(interface).await
+    }
+
+    async fn delete_prio(&self, interface: &str) -> anyhow::Result<()> {
+        netlink::delete_prio(interface).await
+    }
+
+    async fn replace_leaf(
+        &self,
+        interface: &str,
+        band: u16,
+        controls: &Controls,
+    ) -> anyhow::Result<()> {
+        netlink::replace_leaf(interface, band, controls).await
+    }
+
+    async fn delete_leaf(&self, interface: &str, band: u16) -> anyhow::Result<()> {
+        netlink::delete_leaf(interface, band).await
+    }
+
+    async fn leaves(&self, interface: &str) -> anyhow::Result<Option<BTreeMap<u16, Controls>>> {
+        netlink::leaves(interface).await
+    }
+
+    async fn add_filter(&self, interface: &str, filter: &Filter) -> anyhow::Result<()> {
+        netlink::add_filter(interface, filter).await
+    }
+
+    async fn delete_filters(&self, interface: &str, priority: u16) -> anyhow::Result<()> {
+        netlink::delete_filters(interface, priority).await
+    }
+
+    async fn filters(&self, interface: &str) -> anyhow::Result<Vec<Filter>> {
+        netlink::filters(interface).await
+    }
+
+    async fn clients(&self) -> anyhow::Result<Vec<Client>> {
+        clients::read().await
+    }
+
+    async fn route(&self, address: IpAddr) -> anyhow::Result<String> {
+        netlink::route(address).await
+    }
+
+    fn context(&self) -> &Context {
+        &self.context
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_aliases() {
+        let output = r"2: ifb-br-lan: <BROADCAST,NOARP,UP,LOWER_UP> mtu 1500 qdisc netem state UNKNOWN mode DEFAULT group default qlen 32
+    link/ether 52:db:07:c1:1d:63 brd ff:ff:ff:ff:ff:ff
+    alias taco:br-lan
+3: ifb0: <BROADCAST,NOARP> mtu 1500 qdisc noop state DOWN mode DEFAULT group default qlen 32
+    link/ether 8e:49:03:62:af:f9 brd ff:ff:ff:ff:ff:ff";
+
+        assert_eq!(
+            output_to_aliases(output),
+            vec![("ifb-br-lan".to_owned(), "taco:br-lan".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_root() {
+        let output = "qdisc fq_codel 8001: root refcnt 2 limit 10240p flows 1024 quantum 1514 target 5ms interval 100ms memory_limit 32Mb ecn drop_batch 64";
+        let root = output_to_root(output).expect("fq_codel");
+        assert_eq!(root.kind, "fq_codel");
+        assert_eq!(root.handle, 0x8001);
+        assert_eq!(
+            root.options,
+            OriginalOptions::Args(
+                "limit 10240 flows 1024 quantum 1514 target 5ms interval 100ms memory_limit 32Mb ecn drop_batch 64"
+                    .split(' ')
+                    .map(String::from)
+                    .collect()
+            )
+        );
+
+        let output = "qdisc cake 800a: dev eth1 root refcnt 9 bandwidth 20Mbit diffserv3 triple-isolate nonat nowash no-ack-filter split-gso rtt 100ms noatm overhead 18";
+        let root = output_to_root(output).expect("cake");
+        assert_eq!((root.kind.as_str(), root.handle), ("cake", 0x800a));
+
+        // captures of qdiscs tc takes the printed options of back
+        let root = output_to_root(include_str!("../../fixtures/tc/iproute2-6.1/tbf.txt"));
+        assert_eq!(
+            root.expect("tbf").options,
+            OriginalOptions::Args(vec![
+                "rate".into(),
+                "1Mbit".into(),
+                "burst".into(),
+                "4Kb".into(),
+                "lat".into(),
+                "400ms".into()
+            ])
+        );
+        let root = output_to_root(include_str!("../../fixtures/tc/iproute2-6.1/pfifo.txt"));
+        assert_eq!(
+            root.expect("pfifo").options,
+            OriginalOptions::Args(vec!["limit".into(), "100".into()])
+        );
+        // htb prints statistics with its options, it is never put back
+        let root = output_to_root(include_str!("../../fixtures/tc/iproute2-6.1/htb.txt"));
+        assert!(root.expect("htb").has_classes());
+
+        // the kernel's default
+        assert_eq!(output_to_root("qdisc noqueue 0: root refcnt 2"), None);
+        assert_eq!(output_to_root("qdisc mq 0: root"), None);
+    }
+
+    #[test]
+    fn test_prio_leaves() {
+        let output = r"qdisc prio 7ac0: root refcnt 2 bands 16 priomap 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0
+qdisc pfifo 7ac1: parent 7ac0:1 limit 1000p
+qdisc netem 7ac2: parent 7ac0:2 limit 1000 delay 200ms
+qdisc pfifo 0: parent 7ac0:3 limit 1000p";
+
+        let leaves = prio_leaves(output_to_qdiscs(output)).expect("taco's prio");
+        assert_eq!(leaves.keys().copied().collect::<Vec<_>>(), vec![2]);
+        assert_eq!(
+            Controls::from_str(&leaves[&2])
+                .ok()
+                .and_then(|c| c.delay)
+                .map(|d| d.time),
+            Some(200.0)
+        );
+
+        let output = "qdisc prio 1: root refcnt 2 bands 3 priomap 1 2 2 2 1 2 0 0 1 1 1 1 1 1 1 1";
+        assert_eq!(prio_leaves(output_to_qdiscs(output)), None);
+    }
+}
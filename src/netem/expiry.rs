@@ -0,0 +1,198 @@
+/// Automatic reverts of Sets
+///
+/// A Set with a TTL snapshots its interface first, and a task puts the
+/// snapshot back once the TTL is over, unless the expiration was extended or
+/// cancelled in between. Further Sets with a TTL only move the deadline, the
+/// interface still goes back to the way it was before the first one.
+///
+/// A Set with a lease is a dead man's switch instead: the interface is reset
+/// unless a heartbeat comes within every lease.
+use super::executor::Executor;
+use super::{NetEm, Snapshot};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// What happens to an interface when its expiration is due
+enum Action {
+    Restore(Box<Snapshot>),
+    Reset,
+}
+
+struct Pending {
+    /// tells the task of the expiration apart from the ones of cancelled
+    /// expirations of the same interface
+    id: u64,
+    deadline: Instant,
+    action: Action,
+    /// time between heartbeats, for a lease
+    lease: Option<Duration>,
+}
+
+/// Pending reverts of the interfaces of an executor
+#[derive(Default)]
+pub struct Expirations {
+    pending: Mutex<HashMap<String, Pending>>,
+}
+
+fn pending(executor: &dyn Executor) -> MutexGuard<'_, HashMap<String, Pending>> {
+    executor
+        .context()
+        .expirations
+        .pending
+        .lock()
+        .expect("poisoned")
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A pending revert of an interface
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Expiration {
+    interface: String,
+    /// seconds left before the revert
+    remaining: f64,
+    /// seconds between heartbeats, for a lease
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lease: Option<f64>,
+}
+
+/// Revert an interface to a snapshot after a TTL, or to the snapshot of a
+/// pending expiration.
+pub fn schedule(executor: Arc<dyn Executor>, interface: &str, snapshot: Snapshot, ttl: Duration) {
+    let deadline = Instant::now() + ttl;
+    {
+        let mut pending = pending(executor.as_ref());
+        if let Some(
+            pending @ Pending {
+                action: Action::Restore(_),
+                ..
+            },
+        ) = pending.get_mut(interface)
+        {
+            pending.deadline = deadline;
+            return;
+        }
+    }
+
+    start(
+        executor,
+        interface,
+        deadline,
+        Action::Restore(Box::new(snapshot)),
+        None,
+    );
+}
+
+/// Reset an interface unless heartbeats come within every lease.
+pub fn lease(executor: Arc<dyn Executor>, interface: &str, lease: Duration) {
+    start(
+        executor,
+        interface,
+        Instant::now() + lease,
+        Action::Reset,
+        Some(lease),
+    );
+}
+
+/// Replace the expiration of an interface.
+fn start(
+    executor: Arc<dyn Executor>,
+    interface: &str,
+    deadline: Instant,
+    action: Action,
+    lease: Option<Duration>,
+) {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    pending(executor.as_ref()).insert(
+        interface.to_owned(),
+        Pending {
+            id,
+            deadline,
+            action,
+            lease,
+        },
+    );
+    tokio::spawn(expire(executor, interface.to_owned(), id, deadline));
+}
+
+async fn expire(executor: Arc<dyn Executor>, interface: String, id: u64, mut deadline: Instant) {
+    let action = loop {
+        tokio::time::sleep_until(deadline).await;
+        let mut pending = pending(executor.as_ref());
+        match pending.get(&interface) {
+            Some(p) if p.id == id && p.deadline <= Instant::now() => {
+                break pending.remove(&interface).map(|p| p.action);
+            }
+            // extended
+            Some(p) if p.id == id => deadline = p.deadline,
+            // cancelled
+            _ => return,
+        }
+    };
+
+    let result = match action {
+        Some(Action::Restore(snapshot)) => {
+            log::info!("Reverting {}, its TTL is over", interface);
+            NetEm::restore(executor.as_ref(), &interface, &snapshot).await
+        }
+        Some(Action::Reset) => {
+            log::warn!("Resetting {}, its lease wasn't renewed", interface);
+            NetEm::reset(executor.as_ref(), &interface).await
+        }
+        None => Ok(()),
+    };
+    if let Err(e) = result {
+        log::error!("Failed to revert {}: {}", interface, e);
+    }
+    super::state::record(executor.as_ref(), &interface).await;
+}
+
+/// Renew the lease of an interface.
+pub fn heartbeat(executor: &dyn Executor, interface: &str) -> anyhow::Result<()> {
+    let mut pending = pending(executor);
+    match pending.get_mut(interface) {
+        Some(Pending {
+            deadline,
+            lease: Some(lease),
+            ..
+        }) => {
+            *deadline = Instant::now() + *lease;
+            Ok(())
+        }
+        _ => Err(anyhow::anyhow!("{} has no lease", interface)),
+    }
+}
+
+/// Postpone the revert of an interface.
+pub fn extend(executor: &dyn Executor, interface: &str, by: Duration) -> anyhow::Result<()> {
+    let mut pending = pending(executor);
+    let pending = pending
+        .get_mut(interface)
+        .ok_or_else(|| anyhow::anyhow!("{} has no pending expiration", interface))?;
+    pending.deadline += by;
+    Ok(())
+}
+
+/// Keep the impairment of an interface, returning whether it was pending.
+pub fn cancel(executor: &dyn Executor, interface: &str) -> bool {
+    pending(executor).remove(interface).is_some()
+}
+
+pub fn list(executor: &dyn Executor) -> Vec<Expiration> {
+    let now = Instant::now();
+    let pending = pending(executor);
+    let mut list = pending
+        .iter()
+        .map(|(interface, p)| Expiration {
+            interface: interface.clone(),
+            remaining: p.deadline.saturating_duration_since(now).as_secs_f64(),
+            lease: p.lease.map(|lease| lease.as_secs_f64()),
+        })
+        .collect::<Vec<_>>();
+    list.sort_by(|a, b| a.interface.cmp(&b.interface));
+    list
+}
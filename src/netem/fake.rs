@@ -0,0 +1,350 @@
+/// In-memory executor
+///
+/// Simulates the qdiscs and IFB devices of a few interfaces, with the errors
+/// tc reports, so that the API can be tested without CAP_NET_ADMIN.
+use super::clients::Client;
+use super::executor::Executor;
+use super::filter::Filter;
+use super::{Context, Controls, Ifb, Original, OriginalOptions, PRIO_MAJOR};
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+#[derive(Default)]
+enum Root {
+    /// the qdisc the kernel attaches by default, which has no handle
+    #[default]
+    Default,
+    Netem(Box<Controls>),
+    /// taco's prio
+    Prio {
+        leaves: BTreeMap<u16, Controls>,
+        filters: Vec<Filter>,
+    },
+    /// a qdisc put there by someone else, like SQM
+    Other(Original),
+}
+
+#[derive(Default)]
+struct Device {
+    root: Root,
+    /// the device ingress traffic is redirected to
+    ingress: Option<String>,
+    /// alias of an IFB device, `None` for other devices
+    ifb: Option<String>,
+}
+
+#[derive(Default)]
+pub struct Fake {
+    devices: Mutex<BTreeMap<String, Device>>,
+    clients: Mutex<Vec<Client>>,
+    context: Context,
+}
+
+impl Fake {
+    pub fn new(interfaces: &[&str]) -> Self {
+        Fake {
+            devices: Mutex::new(
+                interfaces
+                    .iter()
+                    .map(|i| (i.to_string(), Device::default()))
+                    .collect(),
+            ),
+            clients: Mutex::default(),
+            context: Context::default(),
+        }
+    }
+
+    /// Add a client, as if it was in the ARP table.
+    pub fn add_client(&self, mac: &str, ip: &str, interface: &str) {
+        self.clients.lock().expect("poisoned").push(Client {
+            mac: mac.parse().expect("invalid MAC address"),
+            ip: ip.parse().expect("invalid IP address"),
+            hostname: None,
+            interface: Some(interface.to_owned()),
+            impaired: false,
+        });
+    }
+
+    /// Replace the root qdisc of an interface, as if someone else did.
+    pub fn add_root(&self, interface: &str, kind: &str, handle: u16, args: &[&str]) {
+        let mut devices = self.devices.lock().expect("poisoned");
+        if let Some(device) = devices.get_mut(interface) {
+            device.root = Root::Other(Original {
+                kind: kind.to_owned(),
+                handle,
+                options: OriginalOptions::Args(args.iter().map(|a| a.to_string()).collect()),
+            });
+        }
+    }
+
+    /// Redirect the ingress traffic of an interface, as if someone else did.
+    pub fn add_ingress(&self, interface: &str, device: &str) {
+        let mut devices = self.devices.lock().expect("poisoned");
+        if let Some(d) = devices.get_mut(interface) {
+            d.ingress = Some(device.to_owned());
+        }
+    }
+
+    /// The kind of the root qdisc of an interface, `None` for the default one.
+    pub fn root_kind(&self, interface: &str) -> Option<String> {
+        let devices = self.devices.lock().expect("poisoned");
+        match &devices.get(interface)?.root {
+            Root::Default => None,
+            Root::Netem(_) => Some("netem".to_owned()),
+            Root::Prio { .. } => Some("prio".to_owned()),
+            Root::Other(original) => Some(original.kind.clone()),
+        }
+    }
+
+    /// The device the ingress traffic of an interface is redirected to.
+    pub fn ingress(&self, interface: &str) -> Option<String> {
+        let devices = self.devices.lock().expect("poisoned");
+        devices.get(interface).and_then(|d| d.ingress.clone())
+    }
+
+    fn with_device<T>(
+        &self,
+        name: &str,
+        f: impl FnOnce(&mut Device) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let mut devices = self.devices.lock().expect("poisoned");
+        match devices.get_mut(name) {
+            Some(device) => f(device),
+            None => Err(anyhow::anyhow!("Cannot find device \"{}\"", name)),
+        }
+    }
+}
+
+#[async_trait]
+impl Executor for Fake {
+    async fn replace(&self, interface: &str, controls: &Controls) -> anyhow::Result<()> {
+        self.with_device(interface, |device| {
+            device.root = Root::Netem(Box::new(controls.clone()));
+            Ok(())
+        })
+    }
+
+    async fn delete(&self, interface: &str) -> anyhow::Result<()> {
+        self.with_device(interface, |device| match device.root {
+            Root::Netem(_) => {
+                device.root = Root::Default;
+                Ok(())
+            }
+            Root::Prio { .. } | Root::Other(_) => {
+                Err(anyhow::anyhow!("Error: Invalid qdisc name."))
+            }
+            Root::Default => Err(anyhow::anyhow!(
+                "Error: Cannot delete qdisc with handle of zero."
+            )),
+        })
+    }
+
+    async fn show(&self, interface: &str) -> anyhow::Result<Controls> {
+        self.with_device(interface, |device| match &device.root {
+            Root::Netem(controls) => Ok(controls.as_ref().clone()),
+            _ => Ok(Controls::default()),
+        })
+    }
+
+    async fn list(&self) -> anyhow::Result<Vec<String>> {
+        // the default qdisc of IFB devices is noop, which isn't dumped
+        let devices = self.devices.lock().expect("poisoned");
+        Ok(devices
+            .iter()
+            .filter(|(_, device)| device.ifb.is_none() || !matches!(device.root, Root::Default))
+            .map(|(name, _)| name.clone())
+            .collect())
+    }
+
+    async fn root(&self, interface: &str) -> anyhow::Result<Option<Original>> {
+        self.with_device(interface, |device| {
+            let taco = |kind: &str, handle| Original {
+                kind: kind.to_owned(),
+                handle,
+                options: OriginalOptions::Args(Vec::new()),
+            };
+            Ok(match &device.root {
+                Root::Default => None,
+                // the kernel picks the handle of a netem added without one
+                Root::Netem(_) => Some(taco("netem", 0x8001)),
+                Root::Prio { .. } => Some(taco("prio", PRIO_MAJOR)),
+                Root::Other(original) => Some(original.clone()),
+            })
+        })
+    }
+
+    async fn restore_root(&self, interface: &str, original: &Original) -> anyhow::Result<()> {
+        self.with_device(interface, |device| {
+            device.root = Root::Other(original.clone());
+            Ok(())
+        })
+    }
+
+    async fn add_ifb(&self, ifb: &Ifb) -> anyhow::Result<()> {
+        let mut devices = self.devices.lock().expect("poisoned");
+        if devices.contains_key(&ifb.name) {
+            return Err(anyhow::anyhow!("RTNETLINK answers: File exists"));
+        }
+        devices.insert(
+            ifb.name.clone(),
+            Device {
+                ifb: Some(ifb.alias()),
+                ..Device::default()
+            },
+        );
+        Ok(())
+    }
+
+    async fn delete_ifb(&self, name: &str) -> anyhow::Result<()> {
+        let mut devices = self.devices.lock().expect("poisoned");
+        match devices.remove(name) {
+            Some(_) => Ok(()),
+            None => Err(anyhow::anyhow!("Cannot find device \"{}\"", name)),
+        }
+    }
+
+    async fn ifbs(&self) -> anyhow::Result<Vec<Ifb>> {
+        let devices = self.devices.lock().expect("poisoned");
+        Ok(devices
+            .iter()
+            .filter_map(|(name, device)| Ifb::from_alias(name, device.ifb.as_deref()?))
+            .collect())
+    }
+
+    async fn redirect_ingress(&self, interface: &str, device: &str) -> anyhow::Result<()> {
+        self.with_device(device, |_| Ok(()))?;
+        self.with_device(interface, |d| {
+            d.ingress = Some(device.to_owned());
+            Ok(())
+        })
+    }
+
+    async fn delete_ingress(&self, interface: &str) -> anyhow::Result<()> {
+        self.with_device(interface, |device| match device.ingress.take() {
+            Some(_) => Ok(()),
+            None => Err(anyhow::anyhow!(
+                "Error: Cannot find specified qdisc on specified device."
+            )),
+        })
+    }
+
+    async fn has_ingress(&self, interface: &str) -> anyhow::Result<bool> {
+        self.with_device(interface, |device| Ok(device.ingress.is_some()))
+    }
+
+    async fn replace_prio(&self, interface: &str) -> anyhow::Result<()> {
+        self.with_device(interface, |device| {
+            if !matches!(device.root, Root::Prio { .. }) {
+                device.root = Root::Prio {
+                    leaves: BTreeMap::new(),
+                    filters: Vec::new(),
+                };
+            }
+            Ok(())
+        })
+    }
+
+    async fn delete_prio(&self, interface: &str) -> anyhow::Result<()> {
+        self.with_device(interface, |device| match device.root {
+            Root::Prio { .. } => {
+                device.root = Root::Default;
+                Ok(())
+            }
+            Root::Netem(_) | Root::Other(_) => Err(anyhow::anyhow!("Error: Invalid qdisc name.")),
+            Root::Default => Err(anyhow::anyhow!(
+                "Error: Cannot delete qdisc with handle of zero."
+            )),
+        })
+    }
+
+    async fn replace_leaf(
+        &self,
+        interface: &str,
+        band: u16,
+        controls: &Controls,
+    ) -> anyhow::Result<()> {
+        self.with_device(interface, |device| match &mut device.root {
+            Root::Prio { leaves, .. } => {
+                leaves.insert(band, controls.clone());
+                Ok(())
+            }
+            _ => Err(anyhow::anyhow!(
+                "Error: Failed to find qdisc with specified classid."
+            )),
+        })
+    }
+
+    async fn delete_leaf(&self, interface: &str, band: u16) -> anyhow::Result<()> {
+        self.with_device(interface, |device| match &mut device.root {
+            Root::Prio { leaves, .. } => match leaves.remove(&band) {
+                Some(_) => Ok(()),
+                None => Err(anyhow::anyhow!(
+                    "Error: Cannot find specified qdisc on specified device."
+                )),
+            },
+            _ => Err(anyhow::anyhow!(
+                "Error: Failed to find qdisc with specified classid."
+            )),
+        })
+    }
+
+    async fn leaves(&self, interface: &str) -> anyhow::Result<Option<BTreeMap<u16, Controls>>> {
+        self.with_device(interface, |device| match &device.root {
+            Root::Prio { leaves, .. } => Ok(Some(leaves.clone())),
+            _ => Ok(None),
+        })
+    }
+
+    async fn add_filter(&self, interface: &str, filter: &Filter) -> anyhow::Result<()> {
+        self.with_device(interface, |device| match &mut device.root {
+            Root::Prio { filters, .. } => {
+                filters.push(filter.clone());
+                Ok(())
+            }
+            _ => Err(anyhow::anyhow!("Error: Parent Qdisc doesn't exists.")),
+        })
+    }
+
+    async fn delete_filters(&self, interface: &str, priority: u16) -> anyhow::Result<()> {
+        self.with_device(interface, |device| match &mut device.root {
+            Root::Prio { filters, .. } => {
+                let count = filters.len();
+                filters.retain(|f| Filter::priority(f.band, f.family) != priority);
+                if filters.len() == count {
+                    return Err(anyhow::anyhow!(
+                        "Error: Filter with specified priority/protocol not found."
+                    ));
+                }
+                Ok(())
+            }
+            _ => Err(anyhow::anyhow!("Error: Parent Qdisc doesn't exists.")),
+        })
+    }
+
+    async fn filters(&self, interface: &str) -> anyhow::Result<Vec<Filter>> {
+        self.with_device(interface, |device| match &device.root {
+            Root::Prio { filters, .. } => Ok(filters.clone()),
+            _ => Ok(Vec::new()),
+        })
+    }
+
+    async fn clients(&self) -> anyhow::Result<Vec<Client>> {
+        Ok(self.clients.lock().expect("poisoned").clone())
+    }
+
+    /// Only clients are reachable.
+    async fn route(&self, address: IpAddr) -> anyhow::Result<String> {
+        let clients = self.clients.lock().expect("poisoned");
+        clients
+            .iter()
+            .find(|client| client.ip == address)
+            .and_then(|client| client.interface.clone())
+            .ok_or_else(|| anyhow::anyhow!("RTNETLINK answers: Network is unreachable"))
+    }
+
+    fn context(&self) -> &Context {
+        &self.context
+    }
+}
@@ -0,0 +1,1028 @@
+/// Per-flow matches
+///
+/// A match selects the traffic of an impairment by addresses, ports and IP
+/// protocol. It is compiled to u32 filters, which every kernel with tc has,
+/// unlike flower: port ranges are split into blocks a mask can select, and
+/// headers are matched at fixed offsets, like `match ip dport` of tc does,
+/// so packets with IP options or IPv6 extension headers don't match ports.
+/// MAC addresses are matched in the Ethernet header, before the IP header.
+use super::{Control, PRIO_MAJOR};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// A single match can't be compiled to more filters than this, which wide
+/// port ranges on both ends would need.
+const MAX_FILTERS: usize = 128;
+
+/// Protocol of filters of packets of any protocol
+pub const ETH_P_ALL: u16 = 0x0003;
+
+/// Offsets of the destination and source MAC addresses, from the IP header
+const DST_MAC: i32 = -14;
+const SRC_MAC: i32 = -8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    Ipv4,
+    Ipv6,
+}
+
+impl Family {
+    pub fn ethertype(self) -> u16 {
+        match self {
+            Family::Ipv4 => 0x0800,
+            Family::Ipv6 => 0x86DD,
+        }
+    }
+
+    pub fn from_ethertype(ethertype: u16) -> Option<Self> {
+        [Family::Ipv4, Family::Ipv6]
+            .into_iter()
+            .find(|f| f.ethertype() == ethertype)
+    }
+
+    /// name of the protocol of a filter for tc
+    fn name(self) -> &'static str {
+        match self {
+            Family::Ipv4 => "ip",
+            Family::Ipv6 => "ipv6",
+        }
+    }
+
+    /// offsets of the source and destination addresses in the IP header
+    fn addresses(self) -> (i32, i32) {
+        match self {
+            Family::Ipv4 => (12, 16),
+            Family::Ipv6 => (8, 24),
+        }
+    }
+
+    /// the key holding the protocol, or next header, and its position in it
+    fn protocol(self) -> (i32, u32) {
+        match self {
+            Family::Ipv4 => (8, 16),
+            Family::Ipv6 => (4, 8),
+        }
+    }
+
+    /// offset of the ports, right after a header without options
+    fn ports(self) -> i32 {
+        match self {
+            Family::Ipv4 => 20,
+            Family::Ipv6 => 40,
+        }
+    }
+}
+
+/// An address or a network, like `10.0.1.0/24` or `fd00::1`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cidr {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    fn family(&self) -> Family {
+        match self.address {
+            IpAddr::V4(_) => Family::Ipv4,
+            IpAddr::V6(_) => Family::Ipv6,
+        }
+    }
+
+    /// the address as 32 bits words and the mask of each
+    fn words(&self) -> Vec<(u32, u32)> {
+        let (address, bits) = match self.address {
+            IpAddr::V4(address) => (u32::from(address) as u128, 32),
+            IpAddr::V6(address) => (u128::from(address), 128),
+        };
+        let mask = u128::MAX
+            .checked_shl(bits - self.prefix as u32)
+            .unwrap_or(0);
+        (0..bits / 32)
+            .rev()
+            .map(|i| ((address >> (i * 32)) as u32, (mask >> (i * 32)) as u32))
+            .collect()
+    }
+
+    fn host(ip: IpAddr) -> Self {
+        let prefix = match ip {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        Cidr {
+            address: ip,
+            prefix,
+        }
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        let cidr = Cidr {
+            address: ip,
+            prefix: self.prefix,
+        };
+        self.family() == cidr.family()
+            && self
+                .words()
+                .into_iter()
+                .zip(cidr.words())
+                .all(|((a, mask), (b, _))| a & mask == b & mask)
+    }
+
+    fn from_words(family: Family, words: &[(u32, u32)]) -> Option<Self> {
+        let (address, mask) = words.iter().fold((0u128, 0u128), |(a, m), (value, mask)| {
+            ((a << 32) | *value as u128, (m << 32) | *mask as u128)
+        });
+        if mask == 0 {
+            return None;
+        }
+
+        let bits = words.len() as u32 * 32;
+        let mask = mask << (128 - bits);
+        let address = match family {
+            Family::Ipv4 => IpAddr::V4(Ipv4Addr::from(address as u32)),
+            Family::Ipv6 => IpAddr::V6(Ipv6Addr::from(address)),
+        };
+        Some(Cidr {
+            address,
+            prefix: mask.leading_ones() as u8,
+        })
+    }
+
+    fn keys(&self, offset: i32) -> Vec<Key> {
+        self.words()
+            .into_iter()
+            .zip((offset..).step_by(4))
+            .filter(|((_, mask), _)| *mask != 0)
+            .map(|((value, mask), offset)| Key {
+                value: value & mask,
+                mask,
+                offset,
+            })
+            .collect()
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let address: IpAddr = address
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid address: '{}'", s))?;
+        let bits = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|p| *p <= bits)
+                .ok_or_else(|| anyhow::anyhow!("Invalid prefix length: '{}'", s))?,
+            None => bits,
+        };
+
+        // host bits are ignored, like tc does
+        let address = match address {
+            IpAddr::V4(a) => {
+                let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(a) & mask))
+            }
+            IpAddr::V6(a) => {
+                let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(a) & mask))
+            }
+        };
+        Ok(Cidr { address, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.address, self.prefix) {
+            (IpAddr::V4(_), 32) | (IpAddr::V6(_), 128) => write!(f, "{}", self.address),
+            (address, prefix) => write!(f, "{}/{}", address, prefix),
+        }
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Cidr> for String {
+    fn from(cidr: Cidr) -> Self {
+        cidr.to_string()
+    }
+}
+
+/// A MAC address, like `a4:83:e7:12:34:56`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub struct MacAddr([u8; 6]);
+
+impl MacAddr {
+    /// the bytes at `offset`, in the aligned 32 bits words holding them
+    fn keys(&self, offset: i32) -> Vec<Key> {
+        let mut keys: Vec<Key> = Vec::new();
+        for (byte, position) in self.0.iter().zip(offset..) {
+            let word = position.div_euclid(4) * 4;
+            let shift = (3 - position.rem_euclid(4)) * 8;
+            if keys.last().map(|k| k.offset) != Some(word) {
+                keys.push(Key {
+                    value: 0,
+                    mask: 0,
+                    offset: word,
+                });
+            }
+            if let Some(key) = keys.last_mut() {
+                key.value |= (*byte as u32) << shift;
+                key.mask |= 0xFF << shift;
+            }
+        }
+        keys
+    }
+
+    /// the address at `offset`, if keys match all of its bytes
+    fn from_keys(keys: &[Key], offset: i32) -> Option<Self> {
+        let mut address = [0; 6];
+        for (byte, position) in address.iter_mut().zip(offset..) {
+            let word = position.div_euclid(4) * 4;
+            let shift = (3 - position.rem_euclid(4)) * 8;
+            let key = keys
+                .iter()
+                .find(|k| k.offset == word && (k.mask >> shift) & 0xFF == 0xFF)?;
+            *byte = (key.value >> shift) as u8;
+        }
+        Some(MacAddr(address))
+    }
+}
+
+impl FromStr for MacAddr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow::anyhow!("Invalid MAC address: '{}'", s);
+        let mut address = [0; 6];
+        let mut parts = s.split([':', '-']);
+        for byte in address.iter_mut() {
+            let part = parts.next().filter(|p| p.len() == 2).ok_or_else(invalid)?;
+            *byte = u8::from_str_radix(part, 16).map_err(|_| invalid())?;
+        }
+        match parts.next() {
+            Some(_) => Err(invalid()),
+            None => Ok(MacAddr(address)),
+        }
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+impl TryFrom<String> for MacAddr {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<MacAddr> for String {
+    fn from(mac: MacAddr) -> Self {
+        mac.to_string()
+    }
+}
+
+/// Ports and protocols are numbers or strings in JSON.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrString<T> {
+    Number(T),
+    String(String),
+}
+
+/// A port, like `22`, or an inclusive range of ports, like `1000-2000`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "NumberOrString<u16>", into = "String")]
+pub struct PortRange {
+    start: u16,
+    end: u16,
+}
+
+impl PortRange {
+    /// `(value, mask)` of the aligned blocks the range is made of
+    fn blocks(self) -> Vec<(u16, u16)> {
+        let mut blocks = Vec::new();
+        let (mut start, end) = (self.start as u32, self.end as u32);
+        while start <= end {
+            // the largest block aligned on `start` that doesn't go past `end`
+            let mut size = 1u32 << start.trailing_zeros().min(16);
+            while start + size - 1 > end {
+                size >>= 1;
+            }
+            blocks.push((start as u16, !(size - 1) as u16));
+            start += size;
+        }
+        blocks
+    }
+
+    fn from_block(value: u16, mask: u16) -> Self {
+        PortRange {
+            start: value & mask,
+            end: value | !mask,
+        }
+    }
+
+    /// The range covering both, if they overlap or are adjacent.
+    fn union(self, other: PortRange) -> Option<PortRange> {
+        if self.start as u32 <= other.end as u32 + 1 && other.start as u32 <= self.end as u32 + 1 {
+            Some(PortRange {
+                start: self.start.min(other.start),
+                end: self.end.max(other.end),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let port = |p: &str| {
+            p.trim()
+                .parse::<u16>()
+                .map_err(|_| anyhow::anyhow!("Invalid port: '{}'", s))
+        };
+        let range = match s.split_once('-') {
+            Some((start, end)) => PortRange {
+                start: port(start)?,
+                end: port(end)?,
+            },
+            None => {
+                let port = port(s)?;
+                PortRange {
+                    start: port,
+                    end: port,
+                }
+            }
+        };
+        if range.start > range.end {
+            return Err(anyhow::anyhow!("Invalid port range: '{}'", s));
+        }
+        Ok(range)
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+impl TryFrom<NumberOrString<u16>> for PortRange {
+    type Error = anyhow::Error;
+
+    fn try_from(value: NumberOrString<u16>) -> Result<Self, Self::Error> {
+        match value {
+            NumberOrString::Number(port) => Ok(PortRange {
+                start: port,
+                end: port,
+            }),
+            NumberOrString::String(s) => s.parse(),
+        }
+    }
+}
+
+impl From<PortRange> for String {
+    fn from(range: PortRange) -> Self {
+        range.to_string()
+    }
+}
+
+/// IP protocol, `icmp` is ICMPv6 for IPv6 traffic.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "NumberOrString<u8>", into = "String")]
+pub enum Protocol {
+    Tcp,
+    Udp,
+    Icmp,
+    Other(u8),
+}
+
+impl Protocol {
+    fn number(self, family: Family) -> u8 {
+        match (self, family) {
+            (Protocol::Tcp, _) => 6,
+            (Protocol::Udp, _) => 17,
+            (Protocol::Icmp, Family::Ipv4) => 1,
+            (Protocol::Icmp, Family::Ipv6) => 58,
+            (Protocol::Other(number), _) => number,
+        }
+    }
+
+    fn from_number(number: u8, family: Family) -> Self {
+        [Protocol::Tcp, Protocol::Udp, Protocol::Icmp]
+            .into_iter()
+            .find(|p| p.number(family) == number)
+            .unwrap_or(Protocol::Other(number))
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tcp" => Ok(Protocol::Tcp),
+            "udp" => Ok(Protocol::Udp),
+            "icmp" | "icmpv6" => Ok(Protocol::Icmp),
+            _ => s
+                .parse()
+                .map(|number| Protocol::from_number(number, Family::Ipv4))
+                .map_err(|_| anyhow::anyhow!("Invalid protocol: '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::Tcp => write!(f, "tcp"),
+            Protocol::Udp => write!(f, "udp"),
+            Protocol::Icmp => write!(f, "icmp"),
+            Protocol::Other(number) => write!(f, "{}", number),
+        }
+    }
+}
+
+impl TryFrom<NumberOrString<u8>> for Protocol {
+    type Error = anyhow::Error;
+
+    fn try_from(value: NumberOrString<u8>) -> Result<Self, Self::Error> {
+        match value {
+            NumberOrString::Number(number) => Ok(Protocol::from_number(number, Family::Ipv4)),
+            NumberOrString::String(s) => s.parse(),
+        }
+    }
+}
+
+impl From<Protocol> for String {
+    fn from(protocol: Protocol) -> Self {
+        protocol.to_string()
+    }
+}
+
+/// Traffic to impair, a missing field matches anything.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Match {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    src: Option<Cidr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dst: Option<Cidr>,
+    /// ports without a protocol match both TCP and UDP
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sport: Option<PortRange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dport: Option<PortRange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    protocol: Option<Protocol>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    src_mac: Option<MacAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dst_mac: Option<MacAddr>,
+}
+
+/// 32 bits of the packet at `offset` from the IP header, masked, must be
+/// equal to `value`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    pub value: u32,
+    pub mask: u32,
+    pub offset: i32,
+}
+
+/// A u32 filter of taco's prio, sending the packets its keys all match to a
+/// band.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    /// `None` for packets of any protocol, matched by MAC addresses only
+    pub family: Option<Family>,
+    pub band: u16,
+    pub keys: Vec<Key>,
+}
+
+impl Filter {
+    /// Filters of both families can't share a priority, each band has one
+    /// for IPv4 and one for IPv6.
+    pub fn priority(band: u16, family: Option<Family>) -> u16 {
+        (band << 1) | (family == Some(Family::Ipv6)) as u16
+    }
+
+    /// the ethertype of the packets the filter applies to
+    pub fn protocol(&self) -> u16 {
+        self.family.map_or(ETH_P_ALL, Family::ethertype)
+    }
+}
+
+/// `protocol PROTOCOL prio PRIORITY u32 match u32 VALUE MASK at OFFSET ...
+/// flowid CLASSID`
+impl Control for Filter {
+    fn to_args(&self) -> Vec<String> {
+        let mut v = vec![
+            "protocol".into(),
+            self.family.map_or("all", Family::name).into(),
+            "prio".into(),
+            Filter::priority(self.band, self.family).to_string(),
+            "u32".into(),
+        ];
+
+        // tc wants at least one key
+        let everything = [Key {
+            value: 0,
+            mask: 0,
+            offset: 0,
+        }];
+        let keys = if self.keys.is_empty() {
+            &everything[..]
+        } else {
+            &self.keys[..]
+        };
+        for key in keys {
+            v.push("match".into());
+            v.push("u32".into());
+            v.push(format!("0x{:08x}", key.value));
+            v.push(format!("0x{:08x}", key.mask));
+            v.push("at".into());
+            v.push(key.offset.to_string());
+        }
+
+        v.push("flowid".into());
+        v.push(format!("{:x}:{:x}", PRIO_MAJOR, self.band));
+        v
+    }
+}
+
+impl Match {
+    /// The traffic sent to a host.
+    pub fn dst_host(ip: IpAddr) -> Self {
+        Match {
+            dst: Some(Cidr::host(ip)),
+            ..Match::default()
+        }
+    }
+
+    /// The traffic sent from a host.
+    pub fn src_host(ip: IpAddr) -> Self {
+        Match {
+            src: Some(Cidr::host(ip)),
+            ..Match::default()
+        }
+    }
+
+    /// The traffic sent to a MAC address.
+    pub fn dst_mac(mac: MacAddr) -> Self {
+        Match {
+            dst_mac: Some(mac),
+            ..Match::default()
+        }
+    }
+
+    /// The traffic sent from a MAC address.
+    pub fn src_mac(mac: MacAddr) -> Self {
+        Match {
+            src_mac: Some(mac),
+            ..Match::default()
+        }
+    }
+
+    /// Whether the match names a client, by its MAC address or by an IP
+    /// network it is in.
+    pub fn names(&self, mac: MacAddr, ip: IpAddr) -> bool {
+        let cidr = |cidr: Option<Cidr>| cidr.is_some_and(|c| c.contains(ip));
+        self.src_mac == Some(mac) || self.dst_mac == Some(mac) || cidr(self.src) || cidr(self.dst)
+    }
+
+    /// The u32 filters sending the matching traffic to a band.
+    pub fn compile(&self, band: u16) -> anyhow::Result<Vec<Filter>> {
+        let ports = self.sport.is_some() || self.dport.is_some();
+        let ip = self.src.is_some() || self.dst.is_some() || self.protocol.is_some() || ports;
+        let mac = self.src_mac.is_some() || self.dst_mac.is_some();
+        let families = match (self.src.map(|c| c.family()), self.dst.map(|c| c.family())) {
+            (Some(src), Some(dst)) if src != dst => {
+                return Err(anyhow::anyhow!(
+                    "src and dst are not of the same IP version"
+                ))
+            }
+            (Some(family), _) | (_, Some(family)) => vec![Some(family)],
+            (None, None) if mac && !ip => vec![None],
+            (None, None) => vec![Some(Family::Ipv4), Some(Family::Ipv6)],
+        };
+
+        let protocols = match self.protocol {
+            Some(Protocol::Icmp) if ports => return Err(anyhow::anyhow!("ICMP has no ports")),
+            None if ports => vec![Some(Protocol::Tcp), Some(Protocol::Udp)],
+            protocol => vec![protocol],
+        };
+
+        // a mask of 0 matches any port
+        let blocks = |range: Option<PortRange>| range.map_or(vec![(0, 0)], PortRange::blocks);
+        let (sports, dports) = (blocks(self.sport), blocks(self.dport));
+
+        let count = families.len() * protocols.len() * sports.len() * dports.len();
+        if count > MAX_FILTERS {
+            return Err(anyhow::anyhow!(
+                "The match needs {} filters, more than {}: narrow its port ranges",
+                count,
+                MAX_FILTERS
+            ));
+        }
+
+        let mut filters = Vec::with_capacity(count);
+        for family in &families {
+            for protocol in &protocols {
+                for sport in &sports {
+                    for dport in &dports {
+                        let mut keys = Vec::new();
+                        if let Some(mac) = &self.src_mac {
+                            keys.append(&mut mac.keys(SRC_MAC));
+                        }
+                        if let Some(mac) = &self.dst_mac {
+                            keys.append(&mut mac.keys(DST_MAC));
+                        }
+                        if let Some(family) = family {
+                            keys.append(&mut self.ip_keys(*family, *protocol, *sport, *dport));
+                        }
+
+                        filters.push(Filter {
+                            family: *family,
+                            band,
+                            keys,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(filters)
+    }
+
+    /// The keys of the IP header of a filter, a port block with a mask of 0
+    /// matches any port.
+    fn ip_keys(
+        &self,
+        family: Family,
+        protocol: Option<Protocol>,
+        (sport, smask): (u16, u16),
+        (dport, dmask): (u16, u16),
+    ) -> Vec<Key> {
+        let (src, dst) = family.addresses();
+        let (protocol_offset, protocol_shift) = family.protocol();
+        let mut keys = Vec::new();
+        if let Some(cidr) = &self.src {
+            keys.append(&mut cidr.keys(src));
+        }
+        if let Some(cidr) = &self.dst {
+            keys.append(&mut cidr.keys(dst));
+        }
+        if let Some(protocol) = protocol {
+            keys.push(Key {
+                value: (protocol.number(family) as u32) << protocol_shift,
+                mask: 0xFF << protocol_shift,
+                offset: protocol_offset,
+            });
+        }
+        if smask != 0 {
+            keys.push(Key {
+                value: (sport as u32) << 16,
+                mask: (smask as u32) << 16,
+                offset: family.ports(),
+            });
+        }
+        if dmask != 0 {
+            keys.push(Key {
+                value: dport as u32,
+                mask: dmask as u32,
+                offset: family.ports(),
+            });
+        }
+        keys
+    }
+
+    /// The traffic a filter matches, keys at other offsets are ignored.
+    fn from_filter(filter: &Filter) -> Self {
+        let mut m = Match {
+            src_mac: MacAddr::from_keys(&filter.keys, SRC_MAC),
+            dst_mac: MacAddr::from_keys(&filter.keys, DST_MAC),
+            ..Match::default()
+        };
+        let family = match filter.family {
+            Some(family) => family,
+            None => return m,
+        };
+        let (src, dst) = family.addresses();
+        let (protocol_offset, protocol_shift) = family.protocol();
+        let words = match family {
+            Family::Ipv4 => 1,
+            Family::Ipv6 => 4,
+        };
+
+        let mut addresses = vec![(0u32, 0u32); words * 2];
+        for key in &filter.keys {
+            let word = |start: i32| {
+                let index = (key.offset - start) / 4;
+                (key.offset >= start && key.offset % 4 == 0 && index < words as i32)
+                    .then_some(index as usize)
+            };
+            if let Some(i) = word(src) {
+                addresses[i] = (key.value, key.mask);
+            } else if let Some(i) = word(dst) {
+                addresses[words + i] = (key.value, key.mask);
+            } else if key.offset == protocol_offset && key.mask == 0xFF << protocol_shift {
+                m.protocol = Some(Protocol::from_number(
+                    (key.value >> protocol_shift) as u8,
+                    family,
+                ));
+            } else if key.offset == family.ports() {
+                let (smask, dmask) = ((key.mask >> 16) as u16, key.mask as u16);
+                if smask != 0 {
+                    m.sport = Some(PortRange::from_block((key.value >> 16) as u16, smask));
+                }
+                if dmask != 0 {
+                    m.dport = Some(PortRange::from_block(key.value as u16, dmask));
+                }
+            }
+        }
+        m.src = Cidr::from_words(family, &addresses[..words]);
+        m.dst = Cidr::from_words(family, &addresses[words..]);
+        m
+    }
+
+    /// The match covering both, if `compile` could have split it into them.
+    fn merge(&self, other: &Match) -> Option<Match> {
+        if self == other {
+            // IPv4 and IPv6 filters of a match without addresses
+            return Some(self.clone());
+        }
+        if self.src != other.src
+            || self.dst != other.dst
+            || self.src_mac != other.src_mac
+            || self.dst_mac != other.dst_mac
+        {
+            return None;
+        }
+
+        if self.protocol == other.protocol {
+            let merged = if self.sport == other.sport {
+                self.dport
+                    .zip(other.dport)
+                    .and_then(|(a, b)| a.union(b))
+                    .map(|dport| Match {
+                        dport: Some(dport),
+                        ..self.clone()
+                    })
+            } else if self.dport == other.dport {
+                self.sport
+                    .zip(other.sport)
+                    .and_then(|(a, b)| a.union(b))
+                    .map(|sport| Match {
+                        sport: Some(sport),
+                        ..self.clone()
+                    })
+            } else {
+                None
+            };
+            return merged;
+        }
+
+        // TCP and UDP filters of ports without a protocol
+        let ports = self.sport.is_some() || self.dport.is_some();
+        let protocols = [self.protocol, other.protocol];
+        if ports
+            && self.sport == other.sport
+            && self.dport == other.dport
+            && protocols.contains(&Some(Protocol::Tcp))
+            && protocols.contains(&Some(Protocol::Udp))
+        {
+            return Some(Match {
+                protocol: None,
+                ..self.clone()
+            });
+        }
+
+        None
+    }
+
+    /// The matches of filters, merging back the ones a match was split into.
+    pub fn from_filters<'a>(filters: impl Iterator<Item = &'a Filter>) -> Vec<Match> {
+        let mut matches = filters.map(Match::from_filter).collect::<Vec<_>>();
+        loop {
+            let merged = (0..matches.len())
+                .flat_map(|i| (i + 1..matches.len()).map(move |j| (i, j)))
+                .find_map(|(i, j)| matches[i].merge(&matches[j]).map(|m| (i, j, m)));
+            match merged {
+                Some((i, j, m)) => {
+                    matches[i] = m;
+                    matches.remove(j);
+                }
+                None => return matches,
+            }
+        }
+    }
+}
+
+static FILTER_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^filter\s.*protocol\s(?P<protocol>ipv6|ip|all)\s.*\su32\s.*\sfh\s[0-9a-f]+:[0-9a-f]*:[0-9a-f]+\s.*\*?flowid\s(?P<major>[0-9a-f]+):(?P<minor>[0-9a-f]+)")
+        .expect("Failed to create regex of filter")
+});
+
+static KEY_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^\s+match\s(?P<value>[0-9a-f]{8})/(?P<mask>[0-9a-f]{8})\sat\s(?P<offset>-?\d+)")
+        .expect("Failed to create regex of key")
+});
+
+/// The u32 filters of taco's prio in the output of `tc filter show`. Its
+/// JSON output prints the keys of a filter as duplicated `match` fields,
+/// which JSON parsers keep only one of.
+pub fn output_to_filters(output: &str) -> Vec<Filter> {
+    let mut filters: Vec<Filter> = Vec::new();
+    let mut current = false;
+    for line in output.lines() {
+        if let Some(captures) = FILTER_REGEX.captures(line) {
+            let family = match &captures["protocol"] {
+                "ip" => Some(Family::Ipv4),
+                "ipv6" => Some(Family::Ipv6),
+                _ => None,
+            };
+            let major = u16::from_str_radix(&captures["major"], 16).ok();
+            let band = u16::from_str_radix(&captures["minor"], 16).ok();
+            current = match (major, band) {
+                (Some(PRIO_MAJOR), Some(band)) => {
+                    filters.push(Filter {
+                        family,
+                        band,
+                        keys: Vec::new(),
+                    });
+                    true
+                }
+                _ => false,
+            };
+        } else if line.starts_with("filter") {
+            current = false;
+        } else if let (true, Some(captures)) = (current, KEY_REGEX.captures(line)) {
+            let key = (
+                u32::from_str_radix(&captures["value"], 16),
+                u32::from_str_radix(&captures["mask"], 16),
+                captures["offset"].parse::<i32>(),
+            );
+            if let (Ok(value), Ok(mask), Ok(offset), Some(filter)) =
+                (key.0, key.1, key.2, filters.last_mut())
+            {
+                if mask != 0 {
+                    filter.keys.push(Key {
+                        value,
+                        mask,
+                        offset,
+                    });
+                }
+            }
+        }
+    }
+    filters
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compile() -> anyhow::Result<()> {
+        let m: Match =
+            serde_json::from_str(r#"{"dst": "10.0.1.7/24", "dport": "1000-2000", "sport": 53}"#)?;
+        assert_eq!(m.dst.map(String::from).as_deref(), Some("10.0.1.0/24"));
+
+        let filters = m.compile(2)?;
+        // 1000-2000 is 8 blocks, for TCP and UDP
+        assert_eq!(filters.len(), 16);
+        assert!(filters.iter().all(|f| f.family == Some(Family::Ipv4)));
+        assert_eq!(
+            filters[0].to_args().join(" "),
+            "protocol ip prio 4 u32 match u32 0x0a000100 0xffffff00 at 16 match u32 0x00060000 0x00ff0000 at 8 match u32 0x00350000 0xffff0000 at 20 match u32 0x000003e8 0x0000fff8 at 20 flowid 7ac0:2"
+        );
+        assert_eq!(Match::from_filters(filters.iter()), vec![m]);
+
+        let m: Match = serde_json::from_str(r#"{"src": "fd00::/8", "protocol": "icmp"}"#)?;
+        let filters = m.compile(3)?;
+        assert_eq!(
+            filters,
+            vec![Filter {
+                family: Some(Family::Ipv6),
+                band: 3,
+                keys: vec![
+                    Key {
+                        value: 0xfd00_0000,
+                        mask: 0xff00_0000,
+                        offset: 8
+                    },
+                    Key {
+                        value: 58 << 8,
+                        mask: 0xff00,
+                        offset: 4
+                    },
+                ],
+            }]
+        );
+        assert_eq!(Match::from_filters(filters.iter()), vec![m]);
+
+        // everything, for both families
+        let filters = Match::default().compile(2)?;
+        assert_eq!(filters.len(), 2);
+        assert_eq!(Match::from_filters(filters.iter()), vec![Match::default()]);
+
+        // a MAC address alone matches packets of any protocol
+        let m = Match::dst_mac("A4:83:E7:12:34:56".parse()?);
+        let filters = m.compile(2)?;
+        assert_eq!(
+            filters[0].to_args().join(" "),
+            "protocol all prio 4 u32 match u32 0x0000a483 0x0000ffff at -16 match u32 0xe7123456 0xffffffff at -12 flowid 7ac0:2"
+        );
+        assert_eq!(Match::from_filters(filters.iter()), vec![m]);
+        let m = Match::src_mac("a4-83-e7-12-34-56".parse()?);
+        assert_eq!(
+            m.compile(2)?[0].keys,
+            vec![
+                Key {
+                    value: 0xa483_e712,
+                    mask: 0xffff_ffff,
+                    offset: -8
+                },
+                Key {
+                    value: 0x3456_0000,
+                    mask: 0xffff_0000,
+                    offset: -4
+                },
+            ]
+        );
+        assert!("a4:83:e7:12:34".parse::<MacAddr>().is_err());
+
+        assert!(
+            serde_json::from_str::<Match>(r#"{"src": "10.0.0.1", "dst": "::1"}"#)?
+                .compile(2)
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_to_filters() {
+        let output = r"filter parent 7ac0: protocol ip pref 4 u32 chain 0
+filter parent 7ac0: protocol ip pref 4 u32 chain 0 fh 800: ht divisor 1
+filter parent 7ac0: protocol ip pref 4 u32 chain 0 fh 800::800 order 2048 key ht 800 bkt 0 *flowid 7ac0:2 not_in_hw
+  match 0a000100/ffffff00 at 12
+  match 00060000/00ff0000 at 8
+  match 00000016/0000ffff at 20
+filter parent 7ac0: protocol ipv6 pref 5 u32 chain 0
+filter parent 7ac0: protocol ipv6 pref 5 u32 chain 0 fh 801: ht divisor 1
+filter parent 7ac0: protocol ipv6 pref 5 u32 chain 0 fh 801::800 order 2048 key ht 801 bkt 0 *flowid 7ac0:2 not_in_hw
+  match 00000000/00000000 at 0";
+
+        let filters = output_to_filters(output);
+        assert_eq!(filters.len(), 2);
+        assert_eq!(filters[1].family, Some(Family::Ipv6));
+        assert!(filters[1].keys.is_empty());
+        assert_eq!(
+            Match::from_filters(filters[..1].iter()),
+            vec![serde_json::from_str::<Match>(
+                r#"{"src": "10.0.1.0/24", "protocol": "tcp", "dport": 22}"#
+            )
+            .unwrap()]
+        );
+    }
+}
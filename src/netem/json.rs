@@ -0,0 +1,419 @@
+/// Parser of `tc -j qdisc show`
+///
+/// The JSON output doesn't depend on spaces or units the way the text output
+/// does: times are in seconds, rates in bytes per second and percentages are
+/// fractions of 1.
+use super::{Controls, Corrupt, Delay, Duplicate, Limit, Loss, Rate, Reorder, Slot};
+use super::{Millisecond, Percentage};
+use serde::Deserialize;
+
+/// A qdisc as printed by `tc -j qdisc show`
+#[derive(Deserialize, Debug)]
+pub struct Qdisc {
+    pub kind: String,
+    /// only printed when no device is given to `show`
+    pub dev: Option<String>,
+    /// like `7ac0:`
+    pub handle: Option<String>,
+    /// like `7ac0:2`, only printed for qdiscs that aren't at the root
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub root: bool,
+    #[serde(default)]
+    pub options: serde_json::Value,
+}
+
+pub fn parse_qdiscs(s: &str) -> anyhow::Result<Vec<Qdisc>> {
+    serde_json::from_str(s).map_err(|e| anyhow::anyhow!("Invalid tc JSON output: {}", e))
+}
+
+fn percentage(fraction: f64) -> Percentage {
+    // tc prints at most 6 significant digits, don't let the conversion add noise
+    (fraction * 100.0 * 1e6).round() / 1e6
+}
+
+fn millisecond(second: f64) -> Millisecond {
+    (second * 1000.0 * 1e6).round() / 1e6
+}
+
+/// 0 means "not set" to netem
+fn non_zero(value: f64) -> Option<f64> {
+    if value == 0.0 {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+#[derive(Deserialize)]
+struct DelayOptions {
+    delay: f64,
+    #[serde(default)]
+    jitter: f64,
+    #[serde(default)]
+    correlation: f64,
+}
+
+#[derive(Deserialize)]
+struct LossRandomOptions {
+    loss: f64,
+    #[serde(default)]
+    correlation: f64,
+}
+
+#[derive(Deserialize)]
+struct LossStateOptions {
+    p13: f64,
+    p31: f64,
+    p32: f64,
+    p23: f64,
+    p14: f64,
+}
+
+#[derive(Deserialize)]
+struct LossGeModelOptions {
+    p: f64,
+    r: f64,
+    #[serde(rename = "1-h")]
+    one_minus_h: f64,
+    #[serde(rename = "1-k")]
+    one_minus_k: f64,
+}
+
+/// `duplicate`, `reorder` and `corrupt` objects
+#[derive(Deserialize)]
+struct ProbabilityOptions {
+    #[serde(alias = "duplicate", alias = "reorder", alias = "corrupt")]
+    percent: f64,
+    #[serde(default)]
+    correlation: f64,
+}
+
+#[derive(Deserialize)]
+struct RateOptions {
+    rate: u64,
+    packetoverhead: Option<i32>,
+    cellsize: Option<u32>,
+    celloverhead: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct SlotOptions {
+    #[serde(rename = "min-delay")]
+    min_delay: Option<f64>,
+    #[serde(rename = "max-delay")]
+    max_delay: Option<f64>,
+    /// delay and jitter of the distribution form
+    #[serde(rename = "distribution")]
+    delay: Option<f64>,
+    jitter: Option<f64>,
+    packets: Option<u32>,
+    bytes: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct NetemOptions {
+    limit: Option<i32>,
+    delay: Option<DelayOptions>,
+    #[serde(rename = "loss-random")]
+    loss_random: Option<LossRandomOptions>,
+    #[serde(rename = "loss-state")]
+    loss_state: Option<LossStateOptions>,
+    #[serde(rename = "loss-gemodel")]
+    loss_gemodel: Option<LossGeModelOptions>,
+    #[serde(default)]
+    ecn: bool,
+    duplicate: Option<ProbabilityOptions>,
+    reorder: Option<ProbabilityOptions>,
+    gap: Option<u32>,
+    corrupt: Option<ProbabilityOptions>,
+    rate: Option<RateOptions>,
+    slot: Option<SlotOptions>,
+}
+
+impl Controls {
+    /// Controls of the options of a netem qdisc in tc JSON output.
+    pub fn from_json(options: &serde_json::Value) -> anyhow::Result<Self> {
+        let options: NetemOptions = serde_json::from_value(options.clone())
+            .map_err(|e| anyhow::anyhow!("Invalid netem options: {}", e))?;
+
+        let delay = options.delay.map(|delay| {
+            let jitter = non_zero(delay.jitter).map(millisecond);
+            Delay {
+                time: millisecond(delay.delay),
+                correlation: jitter.and(non_zero(delay.correlation).map(percentage)),
+                jitter,
+                distribution: None,
+            }
+        });
+
+        let ecn = options.ecn;
+        let loss = if let Some(random) = options.loss_random {
+            Some(Loss::Random {
+                percent: percentage(random.loss),
+                correlation: non_zero(random.correlation).map(percentage),
+                ecn,
+            })
+        } else if let Some(state) = options.loss_state {
+            Some(Loss::State {
+                p13: percentage(state.p13),
+                p31: Some(percentage(state.p31)),
+                p32: Some(percentage(state.p32)),
+                p23: Some(percentage(state.p23)),
+                p14: Some(percentage(state.p14)),
+                ecn,
+            })
+        } else {
+            options.loss_gemodel.map(|gemodel| Loss::GeModel {
+                p: percentage(gemodel.p),
+                r: Some(percentage(gemodel.r)),
+                one_minus_h: Some(percentage(gemodel.one_minus_h)),
+                one_minus_k: Some(percentage(gemodel.one_minus_k)),
+                ecn,
+            })
+        };
+
+        let duplicate = options.duplicate.map(|duplicate| Duplicate {
+            percent: percentage(duplicate.percent),
+            correlation: non_zero(duplicate.correlation).map(percentage),
+        });
+
+        let gap = options.gap.filter(|gap| *gap > 0);
+        let reorder = options.reorder.map(|reorder| Reorder {
+            percent: percentage(reorder.percent),
+            correlation: non_zero(reorder.correlation).map(percentage),
+            distance: gap,
+        });
+
+        let corrupt = options.corrupt.map(|corrupt| Corrupt {
+            percent: percentage(corrupt.percent),
+            correlation: non_zero(corrupt.correlation).map(percentage),
+        });
+
+        let rate = options.rate.map(|rate| Rate {
+            rate: rate.rate.saturating_mul(8),
+            // printed as 0 when not set, unlike in the text output
+            packetoverhead: rate.packetoverhead.filter(|overhead| *overhead != 0),
+            cellsize: rate.cellsize.filter(|cellsize| *cellsize != 0),
+            celloverhead: rate.celloverhead.filter(|overhead| *overhead != 0),
+        });
+
+        // the JSON output has 0 for the limits the text output leaves out
+        let slot = options.slot.map(|slot| SlotOptions {
+            packets: slot.packets.filter(|packets| *packets > 0),
+            bytes: slot.bytes.filter(|bytes| *bytes > 0),
+            ..slot
+        });
+        let slot = match slot {
+            Some(SlotOptions {
+                delay: Some(delay),
+                jitter: Some(jitter),
+                packets,
+                bytes,
+                ..
+            }) => Some(Slot::Distribution {
+                distribution: None,
+                delay: millisecond(delay),
+                jitter: millisecond(jitter),
+                packets,
+                bytes,
+            }),
+            Some(SlotOptions {
+                min_delay: Some(min_delay),
+                max_delay,
+                packets,
+                bytes,
+                ..
+            }) => Some(Slot::Range {
+                min_delay: millisecond(min_delay),
+                max_delay: max_delay.map(millisecond),
+                packets,
+                bytes,
+            }),
+            _ => None,
+        };
+
+        Ok(Controls {
+            limit: options.limit.map(|packets| Limit { packets }),
+            delay,
+            loss,
+            corrupt,
+            duplicate,
+            reorder,
+            rate,
+            slot,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    fn netem(json: &str) -> anyhow::Result<Controls> {
+        let qdiscs = parse_qdiscs(json)?;
+        let netem = qdiscs
+            .iter()
+            .find(|q| q.kind == "netem" && q.root)
+            .ok_or_else(|| anyhow::anyhow!("no netem"))?;
+        Controls::from_json(&netem.options)
+    }
+
+    #[test]
+    fn test_iproute2_6_1() -> anyhow::Result<()> {
+        let qdiscs = parse_qdiscs(include_str!("../../fixtures/tc/iproute2-6.1/list.json"))?;
+        assert_eq!(qdiscs.len(), 2);
+        assert_eq!(qdiscs[1].dev.as_deref(), Some("eth0"));
+        assert_eq!(qdiscs[1].kind, "pfifo_fast");
+
+        let qdiscs = parse_qdiscs(include_str!("../../fixtures/tc/iproute2-6.1/htb.json"))?;
+        assert!(qdiscs[0].root && qdiscs[0].dev.is_none());
+        assert_eq!(qdiscs[0].kind, "htb");
+
+        // a root without netem has no controls
+        for json in [
+            include_str!("../../fixtures/tc/iproute2-6.1/tbf.json"),
+            include_str!("../../fixtures/tc/iproute2-6.1/pfifo.json"),
+        ] {
+            assert!(parse_qdiscs(json)?.iter().all(|q| q.kind != "netem"));
+        }
+        for text in [
+            include_str!("../../fixtures/tc/iproute2-6.1/tbf.txt"),
+            include_str!("../../fixtures/tc/iproute2-6.1/pfifo.txt"),
+        ] {
+            assert_eq!(Controls::from_str(text)?, Controls::default());
+        }
+
+        Ok(())
+    }
+
+    /// The same qdisc in both outputs of a version of tc
+    fn capture(json: &str, text: &str) -> anyhow::Result<Controls> {
+        let controls = netem(json)?;
+        assert_eq!(controls, Controls::from_str(text)?);
+        Ok(controls)
+    }
+
+    #[test]
+    fn test_netem() -> anyhow::Result<()> {
+        let controls = capture(
+            include_str!("../../fixtures/tc/iproute2-6.1/netem.json"),
+            include_str!("../../fixtures/tc/iproute2-6.1/netem.txt"),
+        )?;
+        assert_eq!(
+            controls,
+            Controls {
+                limit: Some(Limit { packets: 1000 }),
+                delay: Some(Delay {
+                    time: 10.0,
+                    jitter: Some(2.0),
+                    correlation: Some(50.0),
+                    // never printed
+                    distribution: None,
+                }),
+                loss: Some(Loss::Random {
+                    percent: 1.0,
+                    correlation: Some(25.0),
+                    ecn: true,
+                }),
+                corrupt: Some(Corrupt {
+                    percent: 0.1,
+                    correlation: None,
+                }),
+                duplicate: Some(Duplicate {
+                    percent: 0.5,
+                    correlation: None,
+                }),
+                reorder: Some(Reorder {
+                    percent: 10.0,
+                    correlation: Some(50.0),
+                    distance: Some(5),
+                }),
+                rate: Some(Rate {
+                    rate: 1_500_000,
+                    packetoverhead: Some(20),
+                    cellsize: Some(53),
+                    celloverhead: Some(-5),
+                }),
+                slot: Some(Slot::Range {
+                    min_delay: 1.0,
+                    max_delay: Some(8.0),
+                    packets: Some(42),
+                    bytes: Some(65536),
+                }),
+            }
+        );
+
+        let controls = capture(
+            include_str!("../../fixtures/tc/iproute2-6.1/netem-gemodel.json"),
+            include_str!("../../fixtures/tc/iproute2-6.1/netem-gemodel.txt"),
+        )?;
+        // tc prints the jitter from the kernel's ticks of 64ns
+        assert_eq!(
+            controls.delay.map(|d| (d.time, d.jitter)),
+            Some((1500.0, Some(0.499)))
+        );
+        assert_eq!(
+            controls.loss,
+            Some(Loss::GeModel {
+                p: 1.0,
+                r: Some(30.0),
+                one_minus_h: Some(90.0),
+                one_minus_k: Some(0.5),
+                ecn: false,
+            })
+        );
+        assert_eq!(
+            controls.slot,
+            Some(Slot::Distribution {
+                distribution: None,
+                delay: 10.0,
+                jitter: 2.0,
+                packets: None,
+                bytes: Some(65536),
+            })
+        );
+
+        let controls = capture(
+            include_str!("../../fixtures/tc/iproute2-6.1/netem-state.json"),
+            include_str!("../../fixtures/tc/iproute2-6.1/netem-state.txt"),
+        )?;
+        assert_eq!(
+            controls.loss,
+            Some(Loss::State {
+                p13: 1.0,
+                p31: Some(30.0),
+                p32: Some(2.0),
+                p23: Some(40.0),
+                p14: Some(0.5),
+                ecn: true,
+            })
+        );
+
+        let controls = capture(
+            include_str!("../../fixtures/tc/iproute2-6.1/netem-rate64.json"),
+            include_str!("../../fixtures/tc/iproute2-6.1/netem-rate64.txt"),
+        )?;
+        assert_eq!(controls.limit, Some(Limit { packets: 5000 }));
+        assert_eq!(controls.rate.map(|r| r.rate), Some(40_000_000_000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy() -> anyhow::Result<()> {
+        // tc builds that know `-j` but predate JSON support in netem print
+        // its options as text in the middle of the JSON document
+        assert!(parse_qdiscs(
+            r#"[{"kind":"netem","handle":"8018:","dev":"br-lan","root":true,"refcnt":2,"options":{limit 1000 delay 10.0ms  2.0ms 50% loss 0.1% 11% duplicate 0.1% 12% reorder 10% 55% corrupt 0.3% 30% rate 10Mbit ecn  gap 5}}]"#
+        )
+        .is_err());
+
+        let text = Controls::from_str(include_str!("../../fixtures/tc/legacy/netem.txt"))?;
+        assert_eq!(text.delay.and_then(|d| d.correlation), Some(50.0));
+        assert_eq!(text.rate.map(|r| r.rate), Some(10_000_000));
+
+        Ok(())
+    }
+}
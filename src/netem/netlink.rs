@@ -0,0 +1,854 @@
+/// netem over rtnetlink
+///
+/// Builds the TCA_OPTIONS of a netem qdisc the way tc does (iproute2
+/// tc/q_netem.c) and decodes the ones dumped by the kernel. Probabilities
+/// are fractions of u32::MAX and times are in nanoseconds.
+use super::filter::{Family, Filter, Key, ETH_P_ALL};
+use super::{Controls, Corrupt, Delay, Distribution, Duplicate, Limit, Loss, Rate, Reorder, Slot};
+use super::{Ifb, Millisecond, Percentage, CLEAN_BAND, PRIO_BANDS, PRIO_MAJOR};
+use super::{Original, OriginalOptions};
+use crate::distribution::TC_LIB_DIR;
+use crate::netlink::{self, Attributes, INGRESS_HANDLE, TC_H_INGRESS, TC_H_ROOT};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+const TCA_NETEM_CORR: u16 = 1;
+const TCA_NETEM_DELAY_DIST: u16 = 2;
+const TCA_NETEM_REORDER: u16 = 3;
+const TCA_NETEM_CORRUPT: u16 = 4;
+const TCA_NETEM_LOSS: u16 = 5;
+const TCA_NETEM_RATE: u16 = 6;
+const TCA_NETEM_ECN: u16 = 7;
+const TCA_NETEM_RATE64: u16 = 8;
+const TCA_NETEM_LATENCY64: u16 = 10;
+const TCA_NETEM_JITTER64: u16 = 11;
+const TCA_NETEM_SLOT: u16 = 12;
+const TCA_NETEM_SLOT_DIST: u16 = 13;
+
+const NETEM_LOSS_GI: u16 = 1;
+const NETEM_LOSS_GE: u16 = 2;
+
+/// most entries the kernel accepts in a distribution table
+const NETEM_DIST_MAX: usize = 16384;
+/// size of struct tc_netem_qopt
+const QOPT_LEN: usize = 24;
+/// the kernel counts time in 64ns ticks in struct tc_netem_qopt
+const PSCHED_SHIFT: u32 = 6;
+/// tc's default queue limit, the kernel would drop everything with 0
+const DEFAULT_LIMIT: u32 = 1000;
+
+fn probability(percent: Percentage) -> u32 {
+    (percent.clamp(0.0, 100.0) / 100.0 * u32::MAX as f64).round() as u32
+}
+
+fn percentage(probability: u32) -> Percentage {
+    (probability as f64 / u32::MAX as f64 * 100.0 * 1e6).round() / 1e6
+}
+
+fn nanosecond(time: Millisecond) -> i64 {
+    (time * 1e6).round() as i64
+}
+
+fn millisecond(nanosecond: i64) -> Millisecond {
+    nanosecond as f64 / 1e6
+}
+
+fn ticks(nanosecond: i64) -> u32 {
+    (nanosecond.max(0) >> PSCHED_SHIFT).min(u32::MAX as i64) as u32
+}
+
+fn non_zero(value: u32) -> Option<u32> {
+    if value == 0 {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+fn to_bytes(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_ne_bytes()).collect()
+}
+
+/// `n` u32 at the start of an attribute, `None` if it is too short
+fn from_bytes(payload: &[u8], n: usize) -> Option<Vec<u32>> {
+    if payload.len() < n * 4 {
+        return None;
+    }
+    Some(
+        payload
+            .chunks_exact(4)
+            .take(n)
+            .map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+    )
+}
+
+fn i64_from_bytes(payload: &[u8]) -> Option<i64> {
+    Some(i64::from_ne_bytes(payload.get(..8)?.try_into().ok()?))
+}
+
+/// Load `TC_LIB_DIR/NAME.dist` like tc does, as the s16 array the kernel expects.
+fn load_distribution(distribution: &Distribution) -> anyhow::Result<Vec<u8>> {
+    let name: String = distribution.clone().into();
+    let path = TC_LIB_DIR.join(format!("{}.dist", name));
+    let content = std::fs::read_to_string(&path).map_err(|e| {
+        anyhow::anyhow!(
+            "No distribution data for {} ({}: {})",
+            name,
+            path.display(),
+            e
+        )
+    })?;
+
+    let mut table = Vec::new();
+    for line in content.lines().filter(|line| !line.starts_with('#')) {
+        for value in line.split_whitespace() {
+            let value: i16 = value.parse().map_err(|e| {
+                anyhow::anyhow!("Invalid value '{}' in {}: {}", value, path.display(), e)
+            })?;
+            table.extend_from_slice(&value.to_ne_bytes());
+        }
+    }
+
+    if table.len() / 2 > NETEM_DIST_MAX {
+        return Err(anyhow::anyhow!(
+            "Too many entries in {}, at most {}",
+            path.display(),
+            NETEM_DIST_MAX
+        ));
+    }
+
+    Ok(table)
+}
+
+impl Controls {
+    /// TCA_OPTIONS of a netem qdisc with these controls.
+    ///
+    /// The correlations, reorder, corrupt, rate, slot and ecn are always
+    /// sent, the kernel would keep their previous values on a replace
+    /// otherwise.
+    pub fn to_netlink(&self) -> anyhow::Result<Vec<u8>> {
+        let latency = self.delay.as_ref().map(|d| nanosecond(d.time)).unwrap_or(0);
+        let jitter = self
+            .delay
+            .as_ref()
+            .and_then(|d| d.jitter)
+            .map(nanosecond)
+            .unwrap_or(0);
+        let delay_correlation = self
+            .delay
+            .as_ref()
+            .filter(|d| d.jitter.is_some())
+            .and_then(|d| d.correlation)
+            .map(probability)
+            .unwrap_or(0);
+
+        let (loss, loss_correlation) = match &self.loss {
+            Some(Loss::Random {
+                percent,
+                correlation,
+                ..
+            }) => (
+                probability(*percent),
+                correlation.map(probability).unwrap_or(0),
+            ),
+            _ => (0, 0),
+        };
+
+        let (duplicate, duplicate_correlation) = match &self.duplicate {
+            Some(duplicate) => (
+                probability(duplicate.percent),
+                duplicate.correlation.map(probability).unwrap_or(0),
+            ),
+            None => (0, 0),
+        };
+
+        // to use reordering, a delay option must be specified.
+        let reorder = self.reorder.as_ref().filter(|_| self.delay.is_some());
+        let (reorder, reorder_correlation, gap) = match reorder {
+            Some(reorder) if reorder.percent > 0.0 => (
+                probability(reorder.percent),
+                reorder.correlation.map(probability).unwrap_or(0),
+                reorder.distance.unwrap_or(1),
+            ),
+            _ => (0, 0, 0),
+        };
+
+        let (corrupt, corrupt_correlation) = match &self.corrupt {
+            Some(corrupt) => (
+                probability(corrupt.percent),
+                corrupt.correlation.map(probability).unwrap_or(0),
+            ),
+            None => (0, 0),
+        };
+
+        let delay_distribution = match self.delay.as_ref().and_then(|d| d.distribution.as_ref()) {
+            Some(_) if latency == 0 || jitter == 0 => {
+                return Err(anyhow::anyhow!(
+                    "distribution specified but no latency and jitter values"
+                ))
+            }
+            Some(distribution) => Some(load_distribution(distribution)?),
+            None => None,
+        };
+
+        let limit = self
+            .limit
+            .as_ref()
+            .map(|l| l.packets as u32)
+            .unwrap_or(DEFAULT_LIMIT);
+        let mut options = Attributes::with_header(&to_bytes(&[
+            ticks(latency),
+            limit,
+            loss,
+            gap,
+            duplicate,
+            ticks(jitter),
+        ]));
+
+        if latency != 0 {
+            options.put(TCA_NETEM_LATENCY64, &latency.to_ne_bytes());
+            options.put(TCA_NETEM_JITTER64, &jitter.to_ne_bytes());
+        }
+
+        options.put(
+            TCA_NETEM_CORR,
+            &to_bytes(&[delay_correlation, loss_correlation, duplicate_correlation]),
+        );
+        if let Some(table) = &delay_distribution {
+            options.put(TCA_NETEM_DELAY_DIST, table);
+        }
+        options.put(
+            TCA_NETEM_REORDER,
+            &to_bytes(&[reorder, reorder_correlation]),
+        );
+        options.put(
+            TCA_NETEM_CORRUPT,
+            &to_bytes(&[corrupt, corrupt_correlation]),
+        );
+
+        let (range, distributed, packets, bytes, slot_distribution) = match &self.slot {
+            Some(Slot::Range {
+                min_delay,
+                max_delay,
+                packets,
+                bytes,
+            }) => (
+                [
+                    nanosecond(*min_delay),
+                    nanosecond(max_delay.unwrap_or(*min_delay)),
+                ],
+                [0, 0],
+                *packets,
+                *bytes,
+                None,
+            ),
+            Some(Slot::Distribution {
+                distribution,
+                delay,
+                jitter,
+                packets,
+                bytes,
+            }) => (
+                [0, 0],
+                [nanosecond(*delay), nanosecond(*jitter)],
+                *packets,
+                *bytes,
+                // tc insists on a table here, normal is the most common one
+                Some(load_distribution(
+                    distribution.as_ref().unwrap_or(&Distribution::Normal),
+                )?),
+            ),
+            None => ([0, 0], [0, 0], None, None, None),
+        };
+        // struct tc_netem_slot, 0 packets or bytes means no limit
+        let mut slot = Vec::with_capacity(40);
+        slot.extend_from_slice(&range[0].to_ne_bytes());
+        slot.extend_from_slice(&range[1].to_ne_bytes());
+        slot.extend_from_slice(&(packets.unwrap_or(0).min(i32::MAX as u32)).to_ne_bytes());
+        slot.extend_from_slice(&(bytes.unwrap_or(0).min(i32::MAX as u32)).to_ne_bytes());
+        slot.extend_from_slice(&distributed[0].to_ne_bytes());
+        slot.extend_from_slice(&distributed[1].to_ne_bytes());
+        options.put(TCA_NETEM_SLOT, &slot);
+
+        if let Some(loss) = &self.loss {
+            match loss {
+                Loss::Random { .. } => {}
+                Loss::State {
+                    p13,
+                    p31,
+                    p32,
+                    p23,
+                    p14,
+                    ..
+                } => {
+                    // the order of struct tc_netem_gimodel
+                    let model = [
+                        probability(*p13),
+                        probability(p31.unwrap_or(100.0 - p13)),
+                        probability(p32.unwrap_or(0.0)),
+                        probability(p14.unwrap_or(0.0)),
+                        probability(p23.unwrap_or(100.0)),
+                    ];
+                    let mut nested = Attributes::new();
+                    nested.put(NETEM_LOSS_GI, &to_bytes(&model));
+                    options.put(TCA_NETEM_LOSS, &nested.into_bytes());
+                }
+                Loss::GeModel {
+                    p,
+                    r,
+                    one_minus_h,
+                    one_minus_k,
+                    ..
+                } => {
+                    // the kernel expects h, not 1-h
+                    let model = [
+                        probability(*p),
+                        probability(r.unwrap_or(100.0 - p)),
+                        u32::MAX - probability(one_minus_h.unwrap_or(100.0)),
+                        probability(one_minus_k.unwrap_or(0.0)),
+                    ];
+                    let mut nested = Attributes::new();
+                    nested.put(NETEM_LOSS_GE, &to_bytes(&model));
+                    options.put(TCA_NETEM_LOSS, &nested.into_bytes());
+                }
+            }
+        }
+
+        let ecn = self.loss.as_ref().map(|l| l.ecn()).unwrap_or(false);
+        options.put_u32(TCA_NETEM_ECN, ecn as u32);
+
+        let rate = self.rate.as_ref();
+        let bytes = rate.map(|r| r.rate / 8).unwrap_or(0);
+        if bytes > u32::MAX as u64 {
+            options.put(TCA_NETEM_RATE64, &bytes.to_ne_bytes());
+        }
+        let rate_bytes = to_bytes(&[
+            bytes.min(u32::MAX as u64) as u32,
+            rate.and_then(|r| r.packetoverhead).unwrap_or(0) as u32,
+            rate.and_then(|r| r.cellsize).unwrap_or(0),
+            rate.and_then(|r| r.celloverhead).unwrap_or(0) as u32,
+        ]);
+        options.put(TCA_NETEM_RATE, &rate_bytes);
+
+        if let Some(table) = &slot_distribution {
+            options.put(TCA_NETEM_SLOT_DIST, table);
+        }
+
+        Ok(options.into_bytes())
+    }
+
+    /// Controls of the TCA_OPTIONS of a netem qdisc dumped by the kernel.
+    pub fn from_netlink(options: &[u8]) -> anyhow::Result<Self> {
+        let qopt = from_bytes(options, QOPT_LEN / 4)
+            .ok_or_else(|| anyhow::anyhow!("Truncated netem options"))?;
+        let (latency_ticks, limit, loss, gap, duplicate, jitter_ticks) =
+            (qopt[0], qopt[1], qopt[2], qopt[3], qopt[4], qopt[5]);
+
+        let mut latency = (latency_ticks as i64) << PSCHED_SHIFT;
+        let mut jitter = (jitter_ticks as i64) << PSCHED_SHIFT;
+        let mut correlation = vec![0; 3];
+        let mut reorder = vec![0; 2];
+        let mut corrupt = vec![0; 2];
+        let mut rate: Option<Vec<u32>> = None;
+        let mut rate64 = None;
+        let mut model = None;
+        let mut ecn = false;
+        let mut slot = None;
+
+        for (kind, payload) in netlink::attributes(&options[QOPT_LEN..]) {
+            match kind {
+                TCA_NETEM_LATENCY64 => latency = i64_from_bytes(payload).unwrap_or(latency),
+                TCA_NETEM_JITTER64 => jitter = i64_from_bytes(payload).unwrap_or(jitter),
+                TCA_NETEM_CORR => correlation = from_bytes(payload, 3).unwrap_or(correlation),
+                TCA_NETEM_REORDER => reorder = from_bytes(payload, 2).unwrap_or(reorder),
+                TCA_NETEM_CORRUPT => corrupt = from_bytes(payload, 2).unwrap_or(corrupt),
+                TCA_NETEM_RATE => rate = from_bytes(payload, 4),
+                TCA_NETEM_RATE64 => {
+                    rate64 = payload
+                        .get(..8)
+                        .and_then(|b| b.try_into().ok())
+                        .map(u64::from_ne_bytes)
+                }
+                TCA_NETEM_LOSS => model = netlink::attributes(payload).next(),
+                TCA_NETEM_ECN => ecn = from_bytes(payload, 1).map(|v| v[0] != 0).unwrap_or(false),
+                TCA_NETEM_SLOT if payload.len() >= 40 => slot = Some(payload),
+                _ => {}
+            }
+        }
+
+        let delay = if latency != 0 {
+            let jitter = if jitter != 0 {
+                Some(millisecond(jitter))
+            } else {
+                None
+            };
+            Some(Delay {
+                time: millisecond(latency),
+                correlation: jitter.and(non_zero(correlation[0]).map(percentage)),
+                jitter,
+                distribution: None,
+            })
+        } else {
+            None
+        };
+
+        let loss = if loss != 0 {
+            Some(Loss::Random {
+                percent: percentage(loss),
+                correlation: non_zero(correlation[1]).map(percentage),
+                ecn,
+            })
+        } else {
+            match model {
+                Some((NETEM_LOSS_GI, payload)) => from_bytes(payload, 5).map(|m| Loss::State {
+                    p13: percentage(m[0]),
+                    p31: Some(percentage(m[1])),
+                    p32: Some(percentage(m[2])),
+                    p14: Some(percentage(m[3])),
+                    p23: Some(percentage(m[4])),
+                    ecn,
+                }),
+                Some((NETEM_LOSS_GE, payload)) => from_bytes(payload, 4).map(|m| Loss::GeModel {
+                    p: percentage(m[0]),
+                    r: Some(percentage(m[1])),
+                    one_minus_h: Some(percentage(u32::MAX - m[2])),
+                    one_minus_k: Some(percentage(m[3])),
+                    ecn,
+                }),
+                _ => None,
+            }
+        };
+
+        let duplicate = non_zero(duplicate).map(|duplicate| Duplicate {
+            percent: percentage(duplicate),
+            correlation: non_zero(correlation[2]).map(percentage),
+        });
+
+        let reorder = non_zero(reorder[0]).map(|percent| Reorder {
+            percent: percentage(percent),
+            correlation: non_zero(reorder[1]).map(percentage),
+            // a reorder without a distance is sent with a gap of 1
+            distance: non_zero(gap).filter(|gap| *gap > 1),
+        });
+
+        let corrupt = non_zero(corrupt[0]).map(|percent| Corrupt {
+            percent: percentage(percent),
+            correlation: non_zero(corrupt[1]).map(percentage),
+        });
+
+        let rate = rate.and_then(|r| {
+            let bytes = rate64.unwrap_or(r[0] as u64);
+            if bytes == 0 {
+                return None;
+            }
+            Some(Rate {
+                rate: bytes.saturating_mul(8),
+                packetoverhead: non_zero(r[1]).map(|v| v as i32),
+                cellsize: non_zero(r[2]),
+                celloverhead: non_zero(r[3]).map(|v| v as i32),
+            })
+        });
+
+        let slot = slot.and_then(|payload| {
+            let i64_at = |i: usize| i64_from_bytes(&payload[i..]).unwrap_or(0);
+            let u32_at = |i: usize| from_bytes(&payload[i..], 1).map(|v| v[0]).unwrap_or(0);
+            let (min_delay, max_delay) = (i64_at(0), i64_at(8));
+            let (packets, bytes) = (non_zero(u32_at(16)), non_zero(u32_at(20)));
+            let (dist_delay, dist_jitter) = (i64_at(24), i64_at(32));
+
+            if dist_jitter > 0 {
+                Some(Slot::Distribution {
+                    distribution: None,
+                    delay: millisecond(dist_delay),
+                    jitter: millisecond(dist_jitter),
+                    packets,
+                    bytes,
+                })
+            } else if min_delay != 0 || max_delay != 0 {
+                Some(Slot::Range {
+                    min_delay: millisecond(min_delay),
+                    max_delay: Some(millisecond(max_delay)),
+                    packets,
+                    bytes,
+                })
+            } else {
+                None
+            }
+        });
+
+        Ok(Controls {
+            limit: Some(Limit {
+                packets: limit as i32,
+            }),
+            delay,
+            loss,
+            corrupt,
+            duplicate,
+            reorder,
+            rate,
+            slot,
+        })
+    }
+}
+
+pub async fn replace(interface: &str, controls: &Controls) -> anyhow::Result<()> {
+    let ifindex = netlink::interface_index(interface)?;
+    let controls = controls.clone();
+    log::info!("Netlink => replace root netem of {}", interface);
+    // the distribution tables are read from files
+    tokio::task::spawn_blocking(move || {
+        let options = controls.to_netlink()?;
+        netlink::replace_qdisc(ifindex, TC_H_ROOT, 0, "netem", options)
+    })
+    .await?
+}
+
+pub async fn delete(interface: &str) -> anyhow::Result<()> {
+    let ifindex = netlink::interface_index(interface)?;
+    log::info!("Netlink => delete root netem of {}", interface);
+    tokio::task::spawn_blocking(move || netlink::delete_qdisc(ifindex, TC_H_ROOT, 0, "netem"))
+        .await?
+}
+
+pub async fn show(interface: &str) -> anyhow::Result<Controls> {
+    let ifindex = netlink::interface_index(interface)?;
+    let qdiscs = tokio::task::spawn_blocking(netlink::dump_qdiscs).await??;
+    match qdiscs
+        .iter()
+        .find(|q| q.ifindex == ifindex && q.parent == TC_H_ROOT && q.kind == "netem")
+    {
+        Some(netem) => Controls::from_netlink(&netem.options),
+        None => Ok(Controls::default()),
+    }
+}
+
+pub async fn list() -> anyhow::Result<Vec<String>> {
+    let qdiscs = tokio::task::spawn_blocking(netlink::dump_qdiscs).await??;
+    Ok(qdiscs
+        .iter()
+        .filter(|q| q.parent == TC_H_ROOT)
+        .filter_map(|q| netlink::interface_name(q.ifindex))
+        .collect())
+}
+
+pub async fn root(interface: &str) -> anyhow::Result<Option<Original>> {
+    let ifindex = netlink::interface_index(interface)?;
+    let qdiscs = tokio::task::spawn_blocking(netlink::dump_qdiscs).await??;
+    Ok(qdiscs
+        .into_iter()
+        .find(|q| q.ifindex == ifindex && q.parent == TC_H_ROOT && q.handle != 0)
+        .map(|q| Original {
+            kind: q.kind,
+            handle: (q.handle >> 16) as u16,
+            options: OriginalOptions::Netlink(q.options),
+        }))
+}
+
+pub async fn restore_root(interface: &str, original: &Original) -> anyhow::Result<()> {
+    let ifindex = netlink::interface_index(interface)?;
+    // the options are replayed as the kernel dumped them
+    let options = match &original.options {
+        OriginalOptions::Netlink(options) => options.clone(),
+        OriginalOptions::Args(_) => {
+            return Err(anyhow::anyhow!(
+                "The options of {} aren't netlink's",
+                original.kind
+            ))
+        }
+    };
+    let (handle, kind) = ((original.handle as u32) << 16, original.kind.clone());
+    log::info!("Netlink => restore root {} of {}", kind, interface);
+    tokio::task::spawn_blocking(move || {
+        netlink::replace_qdisc(ifindex, TC_H_ROOT, handle, &kind, options)
+    })
+    .await?
+}
+
+pub async fn add_ifb(ifb: &Ifb) -> anyhow::Result<()> {
+    let (name, alias) = (ifb.name.clone(), ifb.alias());
+    log::info!("Netlink => add {} as {}", name, alias);
+    tokio::task::spawn_blocking(move || {
+        netlink::add_link(&name, "ifb")?;
+        netlink::set_link_up(netlink::interface_index(&name)?, &alias)
+    })
+    .await?
+}
+
+pub async fn delete_link(name: &str) -> anyhow::Result<()> {
+    let index = netlink::interface_index(name)?;
+    log::info!("Netlink => delete link {}", name);
+    tokio::task::spawn_blocking(move || netlink::delete_link(index)).await?
+}
+
+pub async fn ifbs() -> anyhow::Result<Vec<Ifb>> {
+    let links = tokio::task::spawn_blocking(netlink::dump_links).await??;
+    Ok(links
+        .iter()
+        .filter(|l| l.kind.as_deref() == Some("ifb"))
+        .filter_map(|l| Ifb::from_alias(&l.name, l.alias.as_deref()?))
+        .collect())
+}
+
+pub async fn redirect_ingress(interface: &str, device: &str) -> anyhow::Result<()> {
+    let ifindex = netlink::interface_index(interface)?;
+    let target = netlink::interface_index(device)?;
+    log::info!("Netlink => redirect ingress of {} to {}", interface, device);
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = netlink::delete_qdisc(ifindex, TC_H_INGRESS, 0, "ingress") {
+            log::debug!("No ingress qdisc to replace: {}", e);
+        }
+        netlink::replace_qdisc(ifindex, TC_H_INGRESS, INGRESS_HANDLE, "ingress", vec![])?;
+        netlink::add_redirect_filter(ifindex, INGRESS_HANDLE, target)
+    })
+    .await?
+}
+
+pub async fn delete_ingress(interface: &str) -> anyhow::Result<()> {
+    let ifindex = netlink::interface_index(interface)?;
+    log::info!("Netlink => delete ingress qdisc of {}", interface);
+    tokio::task::spawn_blocking(move || netlink::delete_qdisc(ifindex, TC_H_INGRESS, 0, "ingress"))
+        .await?
+}
+
+pub async fn has_ingress(interface: &str) -> anyhow::Result<bool> {
+    let ifindex = netlink::interface_index(interface)?;
+    let qdiscs = tokio::task::spawn_blocking(netlink::dump_qdiscs).await??;
+    Ok(qdiscs
+        .iter()
+        .any(|q| q.ifindex == ifindex && q.parent == TC_H_INGRESS))
+}
+
+/// `7ac0:` and `7ac0:2`
+fn prio_handle(band: u16) -> u32 {
+    ((PRIO_MAJOR as u32) << 16) | band as u32
+}
+
+/// `7ac2:`, leaves need handles of their own
+fn leaf_handle(band: u16) -> u32 {
+    ((PRIO_MAJOR + band) as u32) << 16
+}
+
+pub async fn replace_prio(interface: &str) -> anyhow::Result<()> {
+    let ifindex = netlink::interface_index(interface)?;
+    log::info!("Netlink => replace root prio of {}", interface);
+    tokio::task::spawn_blocking(move || {
+        // struct tc_prio_qopt, everything goes to the first band
+        let mut options = (PRIO_BANDS as i32).to_ne_bytes().to_vec();
+        options.extend_from_slice(&[0u8; 16]);
+        netlink::replace_qdisc(ifindex, TC_H_ROOT, prio_handle(0), "prio", options)?;
+
+        // the default pfifo of a band is as long as the transmit queue,
+        // which is 32 packets for IFB devices
+        let limit = DEFAULT_LIMIT.to_ne_bytes().to_vec();
+        netlink::replace_qdisc(
+            ifindex,
+            prio_handle(CLEAN_BAND),
+            leaf_handle(CLEAN_BAND),
+            "pfifo",
+            limit,
+        )
+    })
+    .await?
+}
+
+pub async fn delete_prio(interface: &str) -> anyhow::Result<()> {
+    let ifindex = netlink::interface_index(interface)?;
+    log::info!("Netlink => delete root prio of {}", interface);
+    tokio::task::spawn_blocking(move || {
+        netlink::delete_qdisc(ifindex, TC_H_ROOT, prio_handle(0), "prio")
+    })
+    .await?
+}
+
+pub async fn replace_leaf(interface: &str, band: u16, controls: &Controls) -> anyhow::Result<()> {
+    let ifindex = netlink::interface_index(interface)?;
+    let controls = controls.clone();
+    log::info!("Netlink => replace netem of band {} of {}", band, interface);
+    tokio::task::spawn_blocking(move || {
+        let options = controls.to_netlink()?;
+        netlink::replace_qdisc(
+            ifindex,
+            prio_handle(band),
+            leaf_handle(band),
+            "netem",
+            options,
+        )
+    })
+    .await?
+}
+
+pub async fn delete_leaf(interface: &str, band: u16) -> anyhow::Result<()> {
+    let ifindex = netlink::interface_index(interface)?;
+    log::info!("Netlink => delete netem of band {} of {}", band, interface);
+    tokio::task::spawn_blocking(move || {
+        netlink::delete_qdisc(ifindex, prio_handle(band), leaf_handle(band), "netem")
+    })
+    .await?
+}
+
+pub async fn leaves(interface: &str) -> anyhow::Result<Option<BTreeMap<u16, Controls>>> {
+    let ifindex = netlink::interface_index(interface)?;
+    let qdiscs = tokio::task::spawn_blocking(netlink::dump_qdiscs).await??;
+    let qdiscs = qdiscs.iter().filter(|q| q.ifindex == ifindex);
+
+    if !qdiscs
+        .clone()
+        .any(|q| q.parent == TC_H_ROOT && q.handle == prio_handle(0) && q.kind == "prio")
+    {
+        return Ok(None);
+    }
+    qdiscs
+        .filter(|q| q.kind == "netem" && q.parent >> 16 == PRIO_MAJOR as u32)
+        .map(|q| Ok((q.parent as u16, Controls::from_netlink(&q.options)?)))
+        .collect::<anyhow::Result<_>>()
+        .map(Some)
+}
+
+pub async fn add_filter(interface: &str, filter: &Filter) -> anyhow::Result<()> {
+    let ifindex = netlink::interface_index(interface)?;
+    let filter = netlink::U32 {
+        protocol: filter.protocol(),
+        priority: Filter::priority(filter.band, filter.family),
+        classid: prio_handle(filter.band),
+        keys: filter
+            .keys
+            .iter()
+            .map(|k| (k.value, k.mask, k.offset))
+            .collect(),
+    };
+    log::info!(
+        "Netlink => add filter of priority {} to {}",
+        filter.priority,
+        interface
+    );
+    tokio::task::spawn_blocking(move || netlink::add_u32_filter(ifindex, prio_handle(0), &filter))
+        .await?
+}
+
+pub async fn delete_filters(interface: &str, priority: u16) -> anyhow::Result<()> {
+    let ifindex = netlink::interface_index(interface)?;
+    log::info!(
+        "Netlink => delete filters of priority {} of {}",
+        priority,
+        interface
+    );
+    tokio::task::spawn_blocking(move || netlink::delete_filters(ifindex, prio_handle(0), priority))
+        .await?
+}
+
+pub async fn filters(interface: &str) -> anyhow::Result<Vec<Filter>> {
+    let ifindex = netlink::interface_index(interface)?;
+    let filters =
+        tokio::task::spawn_blocking(move || netlink::dump_u32_filters(ifindex, prio_handle(0)))
+            .await??;
+    Ok(filters
+        .into_iter()
+        .filter(|f| f.classid >> 16 == PRIO_MAJOR as u32)
+        .filter_map(|f| {
+            Some(Filter {
+                family: match f.protocol {
+                    ETH_P_ALL => None,
+                    protocol => Some(Family::from_ethertype(protocol)?),
+                },
+                band: f.classid as u16,
+                keys: f
+                    .keys
+                    .into_iter()
+                    .filter(|(_, mask, _)| *mask != 0)
+                    .map(|(value, mask, offset)| Key {
+                        value,
+                        mask,
+                        offset,
+                    })
+                    .collect(),
+            })
+        })
+        .collect())
+}
+
+pub async fn route(address: IpAddr) -> anyhow::Result<String> {
+    let index = tokio::task::spawn_blocking(move || netlink::route(address)).await??;
+    netlink::interface_name(index)
+        .ok_or_else(|| anyhow::anyhow!("Cannot find device of index {}", index))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() -> anyhow::Result<()> {
+        let controls: Controls = serde_json::from_str(
+            r#"{
+                "limit": {"packets": 2000},
+                "delay": {"time": 10, "jitter": 2, "correlation": 50},
+                "loss": {"p": 1, "r": 30, "1-h": 90, "ecn": true},
+                "duplicate": {"percent": 0.1},
+                "reorder": {"percent": 10, "correlation": 55, "distance": 5},
+                "corrupt": {"percent": 0.3, "correlation": 30},
+                "rate": {"rate": 10000000000, "packetoverhead": -4},
+                "slot": {"min_delay": 1, "max_delay": 8, "packets": 42}
+            }"#,
+        )?;
+
+        let decoded = Controls::from_netlink(&controls.to_netlink()?)?;
+        // 1-k defaults to 0, the kernel gets h from 1-h
+        assert_eq!(
+            decoded.loss,
+            Some(Loss::GeModel {
+                p: 1.0,
+                r: Some(30.0),
+                one_minus_h: Some(90.0),
+                one_minus_k: Some(0.0),
+                ecn: true,
+            })
+        );
+        assert_eq!(decoded.limit, controls.limit);
+        assert_eq!(decoded.delay, controls.delay);
+        assert_eq!(decoded.duplicate, controls.duplicate);
+        assert_eq!(decoded.reorder, controls.reorder);
+        assert_eq!(decoded.corrupt, controls.corrupt);
+        assert_eq!(decoded.rate, controls.rate);
+        assert_eq!(decoded.slot, controls.slot);
+
+        // in the order of struct tc_netem_gimodel, p14 defaults to 0
+        let controls: Controls =
+            serde_json::from_str(r#"{"loss": {"p13": 5, "p31": 40, "p32": 20, "p23": 70}}"#)?;
+        assert_eq!(
+            Controls::from_netlink(&controls.to_netlink()?)?.loss,
+            Some(Loss::State {
+                p13: 5.0,
+                p31: Some(40.0),
+                p32: Some(20.0),
+                p23: Some(70.0),
+                p14: Some(0.0),
+                ecn: false,
+            })
+        );
+        let controls: Controls = serde_json::from_str(
+            r#"{"loss": {"p13": 5, "p31": 40, "p32": 20, "p23": 70, "p14": 1, "ecn": true}}"#,
+        )?;
+        assert_eq!(
+            Controls::from_netlink(&controls.to_netlink()?)?.loss,
+            controls.loss
+        );
+
+        // a gap of 1 is the default distance
+        let controls: Controls =
+            serde_json::from_str(r#"{"delay": {"time": 10}, "reorder": {"percent": 25}}"#)?;
+        assert_eq!(
+            Controls::from_netlink(&controls.to_netlink()?)?.reorder,
+            controls.reorder
+        );
+
+        // reordering without a delay is dropped, like with tc
+        let controls: Controls = serde_json::from_str(r#"{"reorder": {"percent": 10}}"#)?;
+        assert_eq!(
+            Controls::from_netlink(&controls.to_netlink()?)?.reorder,
+            None
+        );
+
+        Ok(())
+    }
+}
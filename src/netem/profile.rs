@@ -0,0 +1,175 @@
+/// Named profiles
+///
+/// A profile names the controls of a typical network, so that "3G" means the
+/// same to every tester. Built-in profiles can't be changed, user-defined
+/// ones are kept in a file when taco is given one, and read back from it at
+/// startup.
+use super::executor::Executor;
+use super::{write_file, Controls};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    controls: Controls,
+}
+
+/// A profile, as listed
+#[derive(Serialize, Debug)]
+pub struct Named {
+    name: String,
+    #[serde(flatten)]
+    profile: Profile,
+    builtin: bool,
+}
+
+/// Delays are added once, on the way out of the interface.
+static BUILTIN: Lazy<BTreeMap<String, Profile>> = Lazy::new(|| {
+    serde_json::from_value(json!({
+        "2g-edge": {
+            "description": "2G EDGE, 240kbit/s",
+            "controls": {"delay": {"time": 400.0, "jitter": 50.0}, "rate": {"rate": 240_000}, "loss": {"percent": 1.0, "ecn": false}},
+        },
+        "3g": {
+            "description": "3G, 1.6Mbit/s",
+            "controls": {"delay": {"time": 150.0, "jitter": 20.0}, "rate": {"rate": 1_600_000}, "loss": {"percent": 0.5, "ecn": false}},
+        },
+        "lte": {
+            "description": "4G LTE, 50Mbit/s",
+            "controls": {"delay": {"time": 50.0, "jitter": 10.0}, "rate": {"rate": 50_000_000}, "loss": {"percent": 0.1, "ecn": false}},
+        },
+        "dsl": {
+            "description": "ADSL, 8Mbit/s",
+            "controls": {"delay": {"time": 25.0, "jitter": 2.0}, "rate": {"rate": 8_000_000}},
+        },
+        "lossy-wifi": {
+            "description": "Wi-Fi far from the access point, bursty loss",
+            "controls": {"delay": {"time": 10.0, "jitter": 8.0}, "rate": {"rate": 20_000_000}, "loss": {"p": 1.0, "r": 25.0, "1-h": 70.0, "ecn": false}},
+        },
+        "geo-satellite": {
+            "description": "Geostationary satellite, 15Mbit/s",
+            "controls": {"delay": {"time": 550.0, "jitter": 20.0}, "rate": {"rate": 15_000_000}, "loss": {"percent": 0.5, "ecn": false}},
+        },
+        "transcontinental": {
+            "description": "A link across a continent or an ocean",
+            "controls": {"delay": {"time": 150.0, "jitter": 5.0}, "loss": {"percent": 0.1, "ecn": false}},
+        },
+    }))
+    .expect("Invalid built-in profiles")
+});
+
+/// User-defined profiles of an executor
+#[derive(Default)]
+pub struct Profiles {
+    profiles: Mutex<BTreeMap<String, Profile>>,
+    /// the file they are kept in
+    file: Mutex<Option<PathBuf>>,
+}
+
+fn profiles(executor: &dyn Executor) -> MutexGuard<'_, BTreeMap<String, Profile>> {
+    executor
+        .context()
+        .profiles
+        .profiles
+        .lock()
+        .expect("poisoned")
+}
+
+/// Keep user-defined profiles in a file, reading the ones already there.
+pub async fn load(executor: &dyn Executor, path: PathBuf) -> anyhow::Result<()> {
+    match tokio::fs::read_to_string(&path).await {
+        Ok(content) => {
+            let profiles: BTreeMap<String, Profile> = serde_json::from_str(&content)
+                .map_err(|e| anyhow::anyhow!("Invalid profiles in {}: {}", path.display(), e))?;
+            log::info!("Loaded {} profiles from {}", profiles.len(), path.display());
+            *self::profiles(executor) = profiles;
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(anyhow::anyhow!("Failed to read {}: {}", path.display(), e)),
+    }
+    *executor.context().profiles.file.lock().expect("poisoned") = Some(path);
+    Ok(())
+}
+
+/// Write the user-defined profiles to their file, if there is one.
+async fn save(executor: &dyn Executor) -> anyhow::Result<()> {
+    let path = match executor
+        .context()
+        .profiles
+        .file
+        .lock()
+        .expect("poisoned")
+        .clone()
+    {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    write_file(&path, || {
+        Ok(serde_json::to_string_pretty(&*profiles(executor))?)
+    })
+    .await
+}
+
+/// The controls of a profile.
+pub fn get(executor: &dyn Executor, name: &str) -> anyhow::Result<Controls> {
+    if let Some(profile) = BUILTIN.get(name) {
+        return Ok(profile.controls.clone());
+    }
+    profiles(executor)
+        .get(name)
+        .map(|profile| profile.controls.clone())
+        .ok_or_else(|| anyhow::anyhow!("No profile {}", name))
+}
+
+/// Add or update a user-defined profile.
+pub async fn set(executor: &dyn Executor, name: &str, profile: &Profile) -> anyhow::Result<()> {
+    if name.is_empty() {
+        return Err(anyhow::anyhow!("A profile has a name"));
+    }
+    if BUILTIN.contains_key(name) {
+        return Err(anyhow::anyhow!("{} is a built-in profile", name));
+    }
+    profiles(executor).insert(name.to_owned(), profile.clone());
+    save(executor).await
+}
+
+pub async fn remove(executor: &dyn Executor, name: &str) -> anyhow::Result<()> {
+    if BUILTIN.contains_key(name) {
+        return Err(anyhow::anyhow!("{} is a built-in profile", name));
+    }
+    let removed = profiles(executor).remove(name);
+    if removed.is_none() {
+        return Err(anyhow::anyhow!("No profile {}", name));
+    }
+    save(executor).await
+}
+
+pub fn find(executor: &dyn Executor, name: &str) -> anyhow::Result<Named> {
+    list(executor)
+        .into_iter()
+        .find(|named| named.name == name)
+        .ok_or_else(|| anyhow::anyhow!("No profile {}", name))
+}
+
+/// Built-in profiles, then user-defined ones.
+pub fn list(executor: &dyn Executor) -> Vec<Named> {
+    let named = |builtin| {
+        move |(name, profile): (&String, &Profile)| Named {
+            name: name.clone(),
+            profile: profile.clone(),
+            builtin,
+        }
+    };
+    let profiles = profiles(executor);
+    BUILTIN
+        .iter()
+        .map(named(true))
+        .chain(profiles.iter().map(named(false)))
+        .collect()
+}
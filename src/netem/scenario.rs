@@ -0,0 +1,261 @@
+/// Timeline scenarios
+///
+/// A scenario walks an interface through steps, each impairing it for some
+/// time, like 2 minutes of good network, then 30s of 500ms latency, then a
+/// 10s outage, and possibly starts over. A task of the interface applies the
+/// steps, and resets the interface once the last one is over or the scenario
+/// is stopped.
+use super::executor::Executor;
+use super::{Controls, Direction, NetEm};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Controls applied for some time
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Step {
+    controls: Controls,
+    /// seconds
+    duration: f64,
+}
+
+impl Step {
+    pub fn new(controls: Controls, duration: f64) -> Self {
+        Step { controls, duration }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Scenario {
+    steps: Vec<Step>,
+    /// start over after the last step, until the scenario is stopped
+    #[serde(rename = "loop", default, skip_serializing_if = "std::ops::Not::not")]
+    repeat: bool,
+    #[serde(default)]
+    direction: Direction,
+}
+
+impl Scenario {
+    pub fn new(steps: Vec<Step>, repeat: bool, direction: Direction) -> Self {
+        Scenario {
+            steps,
+            repeat,
+            direction,
+        }
+    }
+}
+
+struct Running {
+    /// tells the task of the scenario apart from the ones of stopped
+    /// scenarios of the same interface
+    id: u64,
+    task: JoinHandle<()>,
+    /// dropped to stop the task once it is done with its current step
+    stop: oneshot::Sender<()>,
+    steps: usize,
+    repeat: bool,
+    step: usize,
+    /// times the scenario started over
+    round: u32,
+    /// end of the current step
+    deadline: Instant,
+}
+
+/// Scenarios running on the interfaces of an executor
+#[derive(Default)]
+pub struct Scenarios {
+    running: Mutex<HashMap<String, Running>>,
+}
+
+fn running(executor: &dyn Executor) -> MutexGuard<'_, HashMap<String, Running>> {
+    executor
+        .context()
+        .scenarios
+        .running
+        .lock()
+        .expect("poisoned")
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Progress of the scenario of an interface
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Status {
+    interface: String,
+    /// index of the current step
+    step: usize,
+    steps: usize,
+    /// seconds left in the current step
+    remaining: f64,
+    #[serde(rename = "loop")]
+    repeat: bool,
+    round: u32,
+}
+
+/// Run a scenario on an interface, in place of the one it may already run.
+/// The traffic of the caller stays clean if it comes `through` the interface.
+pub async fn start(
+    executor: Arc<dyn Executor>,
+    interface: &str,
+    scenario: &Scenario,
+    through: Option<IpAddr>,
+) -> anyhow::Result<()> {
+    if scenario.steps.is_empty() {
+        return Err(anyhow::anyhow!("A scenario has at least one step"));
+    }
+    let steps = scenario
+        .steps
+        .iter()
+        .map(|step| {
+            let duration = Duration::try_from_secs_f64(step.duration)
+                .map_err(|e| anyhow::anyhow!("Invalid duration: {}", e))?;
+            Ok((step.controls.clone(), duration))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if scenario.repeat && steps.iter().all(|(_, duration)| duration.is_zero()) {
+        return Err(anyhow::anyhow!("A looping scenario can't last no time"));
+    }
+
+    cancel(executor.as_ref(), interface).await;
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let (stop, stopped) = oneshot::channel();
+    // the task waits for its entry behind the lock
+    let mut running = running(executor.as_ref());
+    let task = tokio::spawn(run(
+        executor.clone(),
+        interface.to_owned(),
+        id,
+        stopped,
+        steps,
+        scenario.repeat,
+        scenario.direction,
+        through,
+    ));
+    running.insert(
+        interface.to_owned(),
+        Running {
+            id,
+            task,
+            stop,
+            steps: scenario.steps.len(),
+            repeat: scenario.repeat,
+            step: 0,
+            round: 0,
+            deadline: Instant::now(),
+        },
+    );
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn run(
+    executor: Arc<dyn Executor>,
+    interface: String,
+    id: u64,
+    mut stopped: oneshot::Receiver<()>,
+    steps: Vec<(Controls, Duration)>,
+    repeat: bool,
+    direction: Direction,
+    through: Option<IpAddr>,
+) {
+    let mut round = 0;
+    'scenario: loop {
+        for (step, (controls, duration)) in steps.iter().enumerate() {
+            let deadline = Instant::now() + *duration;
+            {
+                let mut running = running(executor.as_ref());
+                match running.get_mut(&interface) {
+                    Some(r) if r.id == id => {
+                        r.step = step;
+                        r.round = round;
+                        r.deadline = deadline;
+                    }
+                    // stopped
+                    _ => return,
+                }
+            }
+
+            log::info!(
+                "Applying step {} of {} of the scenario of {}",
+                step + 1,
+                steps.len(),
+                interface
+            );
+            let result = NetEm::set(
+                executor.as_ref(),
+                &interface,
+                controls,
+                direction,
+                None,
+                through,
+            )
+            .await;
+            if let Err(e) = result {
+                log::error!("Failed to apply the scenario of {}: {}", interface, e);
+                break 'scenario;
+            }
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline) => {}
+                _ = &mut stopped => return,
+            }
+        }
+        if !repeat {
+            break;
+        }
+        round += 1;
+    }
+
+    {
+        let mut running = running(executor.as_ref());
+        match running.get(&interface) {
+            Some(r) if r.id == id => running.remove(&interface),
+            _ => return,
+        };
+    }
+    log::info!("Resetting {}, its scenario is over", interface);
+    if let Err(e) = NetEm::reset(executor.as_ref(), &interface).await {
+        log::error!("Failed to reset {}: {}", interface, e);
+    }
+}
+
+/// Stop the scenario of an interface, leaving the interface as it is.
+/// Returns whether there was one.
+pub async fn cancel(executor: &dyn Executor, interface: &str) -> bool {
+    let running = running(executor).remove(interface);
+    match running {
+        Some(running) => {
+            // a step being applied isn't cut short, nor applied after what
+            // the caller does next
+            drop(running.stop);
+            if let Err(e) = running.task.await {
+                log::error!("The scenario of {} failed: {}", interface, e);
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn list(executor: &dyn Executor) -> Vec<Status> {
+    let now = Instant::now();
+    let running = running(executor);
+    let mut list = running
+        .iter()
+        .map(|(interface, r)| Status {
+            interface: interface.clone(),
+            step: r.step,
+            steps: r.steps,
+            remaining: r.deadline.saturating_duration_since(now).as_secs_f64(),
+            repeat: r.repeat,
+            round: r.round,
+        })
+        .collect::<Vec<_>>();
+    list.sort_by(|a, b| a.interface.cmp(&b.interface));
+    list
+}
@@ -0,0 +1,166 @@
+/// Desired state
+///
+/// taco records the impairment every interface should have after each change
+/// made through the API. With a state file, it keeps them there and puts
+/// back whatever is missing when it starts, like after a reboot of the
+/// router. Shutting down doesn't change what interfaces should have.
+/// Scenarios and traces aren't recorded, an interface running one should
+/// have no impairment.
+///
+/// The root qdiscs taco replaced are kept in a second file next to it, with
+/// the `originals.json` extension, to be put back even after a restart.
+use super::executor::Executor;
+use super::{touch, write_file, NetEm, Snapshot};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+/// Desired state of the interfaces of an executor
+#[derive(Default)]
+pub struct State {
+    /// the file the state is kept in
+    file: Mutex<Option<PathBuf>>,
+    /// impairments interfaces should have
+    desired: Mutex<BTreeMap<String, Snapshot>>,
+}
+
+fn desired_of(executor: &dyn Executor) -> MutexGuard<'_, BTreeMap<String, Snapshot>> {
+    executor.context().state.desired.lock().expect("poisoned")
+}
+
+/// Keep the state in a file, and put back the impairments it has that
+/// interfaces are missing.
+pub async fn load(executor: &dyn Executor, path: PathBuf) -> anyhow::Result<()> {
+    let desired: BTreeMap<String, Snapshot> = match tokio::fs::read_to_string(&path).await {
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Invalid state in {}: {}", path.display(), e))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(e) => return Err(anyhow::anyhow!("Failed to read {}: {}", path.display(), e)),
+    };
+
+    let originals_path = originals_file(&path);
+    match tokio::fs::read_to_string(&originals_path).await {
+        Ok(content) => {
+            *executor.context().originals.lock().expect("poisoned") = serde_json::from_str(&content)
+                .map_err(|e| {
+                    anyhow::anyhow!("Invalid originals in {}: {}", originals_path.display(), e)
+                })?
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(anyhow::anyhow!(
+                "Failed to read {}: {}",
+                originals_path.display(),
+                e
+            ))
+        }
+    }
+    // roots replaced while putting back impairments are kept too
+    *desired_of(executor) = desired.clone();
+    *executor.context().state.file.lock().expect("poisoned") = Some(path);
+
+    for (interface, snapshot) in &desired {
+        match NetEm::snapshot(executor, interface).await {
+            Ok(current) if current == *snapshot => {
+                log::info!("{} is impaired already", interface)
+            }
+            Ok(_) => {
+                log::info!("Putting back the impairment of {}", interface);
+                match NetEm::restore(executor, interface, snapshot).await {
+                    Ok(()) => touch(executor, interface),
+                    Err(e) => {
+                        log::error!("Failed to put back the impairment of {}: {}", interface, e)
+                    }
+                }
+            }
+            // it may come up later, keep it in the state
+            Err(e) => log::warn!("Failed to read the impairment of {}: {}", interface, e),
+        }
+    }
+    Ok(())
+}
+
+/// Record the impairment an interface has as the one it should have.
+pub async fn record(executor: &dyn Executor, interface: &str) {
+    let snapshot = match NetEm::snapshot(executor, interface).await {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            log::error!("Failed to record the impairment of {}: {}", interface, e);
+            return;
+        }
+    };
+
+    {
+        let mut desired = desired_of(executor);
+        if snapshot == Snapshot::default() {
+            desired.remove(interface);
+        } else {
+            desired.insert(interface.to_owned(), snapshot);
+        }
+    }
+    save(executor).await;
+}
+
+/// Record that an interface should have no impairment.
+pub async fn forget(executor: &dyn Executor, interface: &str) {
+    let removed = desired_of(executor).remove(interface).is_some();
+    if removed {
+        save(executor).await;
+    }
+}
+
+/// The impairment an interface should have, `None` when it should have
+/// none.
+pub fn desired(executor: &dyn Executor, interface: &str) -> Option<Snapshot> {
+    desired_of(executor).get(interface).cloned()
+}
+
+/// Impaired interfaces, with the impairment they should have.
+pub fn all(executor: &dyn Executor) -> BTreeMap<String, Snapshot> {
+    desired_of(executor).clone()
+}
+
+async fn save(executor: &dyn Executor) {
+    let path = match executor
+        .context()
+        .state
+        .file
+        .lock()
+        .expect("poisoned")
+        .clone()
+    {
+        Some(path) => path,
+        None => return,
+    };
+    let content = || Ok(serde_json::to_string_pretty(&*desired_of(executor))?);
+    if let Err(e) = write_file(&path, content).await {
+        log::error!("Failed to save the state: {}", e);
+    }
+}
+
+fn originals_file(path: &Path) -> PathBuf {
+    path.with_extension("originals.json")
+}
+
+/// Keep the root qdiscs taco replaced next to the state file, if any.
+pub async fn save_originals(executor: &dyn Executor) {
+    let path = match executor
+        .context()
+        .state
+        .file
+        .lock()
+        .expect("poisoned")
+        .clone()
+    {
+        Some(path) => originals_file(&path),
+        None => return,
+    };
+    let content = || {
+        Ok(serde_json::to_string_pretty(
+            &*executor.context().originals.lock().expect("poisoned"),
+        )?)
+    };
+    if let Err(e) = write_file(&path, content).await {
+        log::error!("Failed to save the original root qdiscs: {}", e);
+    }
+}
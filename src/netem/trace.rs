@@ -0,0 +1,312 @@
+/// Trace-driven replay
+///
+/// A trace is a list of timestamped samples of the rate, delay and loss of a
+/// network, like the ones measured on field drives. It is replayed as a
+/// scenario whose steps set the samples one after the other, on top of some
+/// base controls.
+///
+/// Two formats are read:
+/// - samples, a line per sample: `SECONDS RATE DELAY LOSS`, with the rate in
+///   kbit/s, the delay in ms and the loss in percent, `-` leaving one out
+/// - Mahimahi packet delivery traces, a line per 1500 bytes packet the link
+///   can deliver, with the millisecond it can be delivered at
+use super::scenario::{Scenario, Step};
+use super::{Controls, Delay, Direction, Loss, Millisecond, Percentage, Rate};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// bytes of the packets of a Mahimahi trace
+const MAHIMAHI_PACKET: u64 = 1500;
+/// milliseconds over which the rate of a Mahimahi trace is averaged, tc
+/// can't keep up with a change per packet
+const MAHIMAHI_WINDOW: u64 = 1000;
+/// milliseconds a Mahimahi trace may last, its windows are counted in memory
+const MAHIMAHI_PERIOD: u64 = 24 * 60 * 60 * 1000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Samples,
+    Mahimahi,
+}
+
+/// A trace to replay on an interface
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Replay {
+    #[serde(default)]
+    format: Format,
+    /// the trace itself, or
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    trace: Option<String>,
+    /// the path of a trace file on the router
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path: Option<PathBuf>,
+    /// how many times faster than real time the trace is played
+    #[serde(default = "Replay::real_time")]
+    speed: f64,
+    /// start over after the end of the trace, until it is stopped
+    #[serde(rename = "loop", default, skip_serializing_if = "std::ops::Not::not")]
+    repeat: bool,
+    /// controls the rate, delay and loss of the samples are set on
+    #[serde(default)]
+    controls: Controls,
+    #[serde(default)]
+    direction: Direction,
+}
+
+/// Rate, delay and loss from some time on, `None` when they aren't
+/// impaired
+#[derive(Debug, Clone, PartialEq)]
+struct Sample {
+    /// seconds since the start of the trace
+    at: f64,
+    /// bit/s, 0 for an outage
+    rate: Option<u64>,
+    delay: Option<Millisecond>,
+    loss: Option<Percentage>,
+}
+
+impl Replay {
+    fn real_time() -> f64 {
+        1.0
+    }
+
+    /// The scenario playing the trace.
+    pub async fn scenario(&self) -> anyhow::Result<Scenario> {
+        if !(self.speed.is_finite() && self.speed > 0.0) {
+            return Err(anyhow::anyhow!("Invalid speed: {}", self.speed));
+        }
+        let content = match (&self.trace, &self.path) {
+            (Some(trace), None) => trace.clone(),
+            (None, Some(path)) => tokio::fs::read_to_string(path)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?,
+            _ => return Err(anyhow::anyhow!("A replay has either a trace or a path")),
+        };
+        let (samples, end) = match self.format {
+            Format::Samples => parse_samples(&content)?,
+            Format::Mahimahi => parse_mahimahi(&content)?,
+        };
+
+        Ok(Scenario::new(
+            steps(&samples, end, &self.controls, self.speed),
+            self.repeat,
+            self.direction,
+        ))
+    }
+}
+
+/// `-` for a value left out
+fn value(s: &str) -> anyhow::Result<Option<f64>> {
+    if s == "-" {
+        return Ok(None);
+    }
+    match s.parse::<f64>() {
+        Ok(value) if value.is_finite() && value >= 0.0 => Ok(Some(value)),
+        _ => Err(anyhow::anyhow!("Invalid value: {}", s)),
+    }
+}
+
+/// Samples of a trace and the time it ends at, the last sample lasting as
+/// long as the one before it, or a second.
+fn parse_samples(s: &str) -> anyhow::Result<(Vec<Sample>, f64)> {
+    let mut samples: Vec<Sample> = Vec::new();
+    for (n, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let columns = line
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|c| !c.is_empty())
+            .collect::<Vec<_>>();
+        let sample = match columns[..] {
+            [at, rate, delay, loss] => Sample {
+                at: value(at)?.ok_or_else(|| anyhow::anyhow!("No time on line {}", n + 1))?,
+                rate: value(rate)?.map(|kbit| (kbit * 1000.0).round() as u64),
+                delay: value(delay)?,
+                loss: value(loss)?,
+            },
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Line {} isn't SECONDS RATE DELAY LOSS",
+                    n + 1
+                ))
+            }
+        };
+        if samples.last().is_some_and(|last| sample.at < last.at) {
+            return Err(anyhow::anyhow!("Line {} goes back in time", n + 1));
+        }
+        samples.push(sample);
+    }
+
+    let end = match &samples[..] {
+        [] => return Err(anyhow::anyhow!("The trace has no samples")),
+        [only] => only.at + 1.0,
+        [.., before, last] => last.at + (last.at - before.at),
+    };
+    Ok((samples, end))
+}
+
+/// Rates of a Mahimahi trace over windows, and the time it ends at, which is
+/// the time of its last packet.
+fn parse_mahimahi(s: &str) -> anyhow::Result<(Vec<Sample>, f64)> {
+    let times = s
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.parse::<u64>()
+                .map_err(|_| anyhow::anyhow!("Invalid timestamp: {}", line))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let period = match times.iter().max() {
+        Some(period) if *period > 0 => *period,
+        _ => return Err(anyhow::anyhow!("The trace has no packets after 0ms")),
+    };
+    if period > MAHIMAHI_PERIOD {
+        return Err(anyhow::anyhow!(
+            "The trace lasts {}ms, more than a day",
+            period
+        ));
+    }
+
+    let windows = period.div_ceil(MAHIMAHI_WINDOW) as usize;
+    let mut packets = vec![0u64; windows];
+    for time in times {
+        packets[((time / MAHIMAHI_WINDOW) as usize).min(windows - 1)] += 1;
+    }
+    let samples = packets
+        .into_iter()
+        .enumerate()
+        .map(|(window, packets)| {
+            let start = window as u64 * MAHIMAHI_WINDOW;
+            let length = MAHIMAHI_WINDOW.min(period - start);
+            Sample {
+                at: start as f64 / 1000.0,
+                rate: Some(packets * MAHIMAHI_PACKET * 8 * 1000 / length),
+                delay: None,
+                loss: None,
+            }
+        })
+        .collect();
+    Ok((samples, period as f64 / 1000.0))
+}
+
+/// Steps setting the samples on the controls, a step per change.
+fn steps(samples: &[Sample], end: f64, controls: &Controls, speed: f64) -> Vec<Step> {
+    let mut steps: Vec<(Controls, f64)> = Vec::new();
+    for (i, sample) in samples.iter().enumerate() {
+        let until = samples.get(i + 1).map_or(end, |next| next.at);
+
+        let mut controls = controls.clone();
+        if let Some(time) = sample.delay {
+            controls.delay = Some(Delay {
+                time,
+                jitter: None,
+                correlation: None,
+                distribution: None,
+            });
+        }
+        if let Some(percent) = sample.loss {
+            controls.loss = Some(Loss::Random {
+                percent,
+                correlation: None,
+                ecn: false,
+            });
+        }
+        match sample.rate {
+            // netem doesn't limit a rate of 0
+            Some(0) => {
+                controls.loss = Some(Loss::Random {
+                    percent: 100.0,
+                    correlation: None,
+                    ecn: false,
+                })
+            }
+            Some(rate) => {
+                controls.rate = Some(Rate {
+                    rate,
+                    packetoverhead: None,
+                    cellsize: None,
+                    celloverhead: None,
+                })
+            }
+            None => {}
+        }
+
+        let duration = (until - sample.at) / speed;
+        match steps.last_mut() {
+            Some((last, last_duration)) if *last == controls => *last_duration += duration,
+            _ => steps.push((controls, duration)),
+        }
+    }
+
+    steps
+        .into_iter()
+        .map(|(controls, duration)| Step::new(controls, duration))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_samples() -> anyhow::Result<()> {
+        let trace = "# seconds, kbit/s, ms, %
+0, 10000, 40, 0
+0.5, 10000, 40, 0
+1.0, 2000, 120.5, 1.5
+2.0, 0, -, -";
+        let (samples, end) = parse_samples(trace)?;
+        assert_eq!(samples.len(), 4);
+        assert_eq!(end, 3.0);
+        assert_eq!(
+            samples[2],
+            Sample {
+                at: 1.0,
+                rate: Some(2_000_000),
+                delay: Some(120.5),
+                loss: Some(1.5),
+            }
+        );
+
+        // equal samples make a single step, played twice as fast
+        let steps = steps(&samples, end, &Controls::default(), 2.0);
+        let steps = serde_json::to_value(steps)?;
+        assert_eq!(
+            steps,
+            serde_json::json!([
+                {"controls": {"delay": {"time": 40.0}, "loss": {"percent": 0.0, "ecn": false}, "rate": {"rate": 10_000_000}}, "duration": 0.5},
+                {"controls": {"delay": {"time": 120.5}, "loss": {"percent": 1.5, "ecn": false}, "rate": {"rate": 2_000_000}}, "duration": 0.5},
+                {"controls": {"loss": {"percent": 100.0, "ecn": false}}, "duration": 0.5},
+            ])
+        );
+
+        assert!(parse_samples("1 2 3").is_err());
+        assert!(parse_samples("1 - - -\n0 - - -").is_err());
+        assert!(parse_samples("").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mahimahi() -> anyhow::Result<()> {
+        // 3 packets in the first second, none in the second one and 1 in
+        // the last half second
+        let (samples, end) = parse_mahimahi("0\n10\n10\n2500\n")?;
+        assert_eq!(end, 2.5);
+        let rates = samples.iter().map(|s| s.rate).collect::<Vec<_>>();
+        assert_eq!(rates, vec![Some(36_000), Some(0), Some(24_000)]);
+        assert_eq!(samples[2].at, 2.0);
+
+        assert!(parse_mahimahi("0\n0\n").is_err());
+        assert!(parse_mahimahi("1.5\n").is_err());
+        // its windows don't fit in memory
+        assert!(parse_mahimahi("18446744073709551615\n").is_err());
+        assert!(parse_mahimahi("86400000\n").is_ok());
+        assert!(parse_mahimahi("86400001\n").is_err());
+        Ok(())
+    }
+}
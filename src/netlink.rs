@@ -0,0 +1,782 @@
+/// Minimal rtnetlink client
+///
+/// Just enough of NETLINK_ROUTE to replace, delete and dump qdiscs without
+/// spawning tc. Refer to linux/netlink.h, linux/rtnetlink.h and
+/// linux/pkt_sched.h for the message layouts.
+use std::ffi::{CStr, CString};
+use std::io;
+use std::net::IpAddr;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_MULTI: u16 = 0x2;
+const NLM_F_ACK: u16 = 0x4;
+const NLM_F_DUMP: u16 = 0x300;
+const NLM_F_REPLACE: u16 = 0x100;
+const NLM_F_EXCL: u16 = 0x200;
+const NLM_F_CREATE: u16 = 0x400;
+/// set on an error message carrying extended ack attributes
+const NLM_F_ACK_TLVS: u16 = 0x200;
+const NLMSGERR_ATTR_MSG: u16 = 1;
+
+const SOL_NETLINK: libc::c_int = 270;
+const NETLINK_CAP_ACK: libc::c_int = 10;
+const NETLINK_EXT_ACK: libc::c_int = 11;
+
+const RTM_NEWLINK: u16 = 16;
+const RTM_DELLINK: u16 = 17;
+const RTM_GETLINK: u16 = 18;
+const RTM_GETROUTE: u16 = 26;
+const RTM_NEWQDISC: u16 = 36;
+const RTM_DELQDISC: u16 = 37;
+const RTM_GETQDISC: u16 = 38;
+const RTM_NEWTFILTER: u16 = 44;
+const RTM_DELTFILTER: u16 = 45;
+const RTM_GETTFILTER: u16 = 46;
+
+const IFLA_IFNAME: u16 = 3;
+const IFLA_LINKINFO: u16 = 18;
+const IFLA_IFALIAS: u16 = 20;
+const IFLA_INFO_KIND: u16 = 1;
+
+const RTA_DST: u16 = 1;
+const RTA_OIF: u16 = 4;
+
+const TCA_KIND: u16 = 1;
+const TCA_OPTIONS: u16 = 2;
+
+const TCA_U32_CLASSID: u16 = 1;
+const TCA_U32_SEL: u16 = 5;
+const TCA_U32_ACT: u16 = 7;
+const TC_U32_TERMINAL: u8 = 1;
+
+const TCA_ACT_KIND: u16 = 1;
+const TCA_ACT_OPTIONS: u16 = 2;
+const TCA_MIRRED_PARMS: u16 = 2;
+const TCA_EGRESS_REDIR: i32 = 1;
+const TC_ACT_STOLEN: i32 = 4;
+
+pub const TC_H_ROOT: u32 = 0xFFFF_FFFF;
+pub const TC_H_INGRESS: u32 = 0xFFFF_FFF1;
+/// `ffff:`, the handle of the ingress qdisc
+pub const INGRESS_HANDLE: u32 = 0xFFFF_0000;
+const ETH_P_ALL: u16 = 0x0003;
+
+const NLMSG_HDRLEN: usize = 16;
+const IFINFOMSG_LEN: usize = 16;
+const TCMSG_LEN: usize = 20;
+const RTMSG_LEN: usize = 12;
+/// struct tc_u32_sel without its keys, and struct tc_u32_key
+const U32_SEL_LEN: usize = 16;
+const U32_KEY_LEN: usize = 16;
+const NLA_HDRLEN: usize = 4;
+/// the type of an attribute without the nested and byte order flags
+const NLA_TYPE_MASK: u16 = 0x3FFF;
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// A buffer of netlink attributes.
+#[derive(Debug, Default)]
+pub struct Attributes(Vec<u8>);
+
+impl Attributes {
+    pub fn new() -> Self {
+        Attributes::default()
+    }
+
+    /// Raw bytes put before the attributes, like the struct that starts
+    /// TCA_OPTIONS of netem.
+    pub fn with_header(header: &[u8]) -> Self {
+        let mut attributes = Attributes(header.to_vec());
+        attributes.0.resize(align(header.len()), 0);
+        attributes
+    }
+
+    pub fn put(&mut self, kind: u16, payload: &[u8]) -> &mut Self {
+        let len = NLA_HDRLEN + payload.len();
+        self.0.extend_from_slice(&(len as u16).to_ne_bytes());
+        self.0.extend_from_slice(&kind.to_ne_bytes());
+        self.0.extend_from_slice(payload);
+        self.0.resize(align(self.0.len()), 0);
+        self
+    }
+
+    pub fn put_u32(&mut self, kind: u16, value: u32) -> &mut Self {
+        self.put(kind, &value.to_ne_bytes())
+    }
+
+    pub fn put_string(&mut self, kind: u16, value: &str) -> &mut Self {
+        let mut payload = value.as_bytes().to_vec();
+        payload.push(0);
+        self.put(kind, &payload)
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+/// Iterate over `(type, payload)` of the attributes in a buffer, stopping
+/// at the first malformed one.
+pub fn attributes(mut buf: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        if buf.len() < NLA_HDRLEN {
+            return None;
+        }
+        let len = u16::from_ne_bytes([buf[0], buf[1]]) as usize;
+        let kind = u16::from_ne_bytes([buf[2], buf[3]]) & NLA_TYPE_MASK;
+        if len < NLA_HDRLEN || len > buf.len() {
+            return None;
+        }
+        let payload = &buf[NLA_HDRLEN..len];
+        buf = &buf[align(len).min(buf.len())..];
+        Some((kind, payload))
+    })
+}
+
+/// A qdisc of a RTM_NEWQDISC message from a dump.
+#[derive(Debug)]
+pub struct Qdisc {
+    pub ifindex: i32,
+    pub handle: u32,
+    pub parent: u32,
+    pub kind: String,
+    /// payload of TCA_OPTIONS, its layout depends on the kind
+    pub options: Vec<u8>,
+}
+
+impl Qdisc {
+    fn parse(payload: &[u8]) -> Option<Self> {
+        if payload.len() < TCMSG_LEN {
+            return None;
+        }
+        let u32_at = |i: usize| {
+            u32::from_ne_bytes([payload[i], payload[i + 1], payload[i + 2], payload[i + 3]])
+        };
+
+        let mut qdisc = Qdisc {
+            ifindex: u32_at(4) as i32,
+            handle: u32_at(8),
+            parent: u32_at(12),
+            kind: String::new(),
+            options: Vec::new(),
+        };
+        for (kind, value) in attributes(&payload[TCMSG_LEN..]) {
+            match kind {
+                TCA_KIND => qdisc.kind = string(value),
+                TCA_OPTIONS => qdisc.options = value.to_vec(),
+                _ => {}
+            }
+        }
+
+        Some(qdisc)
+    }
+}
+
+/// A u32 filter, its keys are `(value, mask, offset)` in host order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct U32 {
+    /// an ethertype, like ETH_P_IP
+    pub protocol: u16,
+    pub priority: u16,
+    pub classid: u32,
+    pub keys: Vec<(u32, u32, i32)>,
+}
+
+impl U32 {
+    /// Parse a RTM_NEWTFILTER message from a dump, `None` for the hash
+    /// tables and filters of other kinds.
+    fn parse(payload: &[u8]) -> Option<Self> {
+        if payload.len() < TCMSG_LEN {
+            return None;
+        }
+        let info = u32::from_ne_bytes([payload[16], payload[17], payload[18], payload[19]]);
+
+        let mut kind = String::new();
+        let mut classid = 0;
+        let mut keys = None;
+        for (attribute, value) in attributes(&payload[TCMSG_LEN..]) {
+            match attribute {
+                TCA_KIND => kind = string(value),
+                TCA_OPTIONS => {
+                    for (option, value) in attributes(value) {
+                        match option {
+                            TCA_U32_CLASSID if value.len() >= 4 => {
+                                classid =
+                                    u32::from_ne_bytes([value[0], value[1], value[2], value[3]])
+                            }
+                            TCA_U32_SEL => keys = parse_selector(value),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if kind != "u32" {
+            return None;
+        }
+
+        Some(U32 {
+            protocol: u16::from_be(info as u16),
+            priority: (info >> 16) as u16,
+            classid,
+            keys: keys?,
+        })
+    }
+}
+
+/// struct tc_u32_sel of a terminal node, with its keys
+fn selector(keys: &[(u32, u32, i32)]) -> Vec<u8> {
+    // flags, offshift, nkeys and padding, then offmask, off, offoff, hoff
+    // and hmask, which are only used to follow headers
+    let mut selector = vec![TC_U32_TERMINAL, 0, keys.len() as u8, 0];
+    selector.extend_from_slice(&[0u8; 12]);
+    for (value, mask, offset) in keys {
+        selector.extend_from_slice(&mask.to_be_bytes());
+        selector.extend_from_slice(&value.to_be_bytes());
+        selector.extend_from_slice(&offset.to_ne_bytes());
+        // offmask
+        selector.extend_from_slice(&[0u8; 4]);
+    }
+    selector
+}
+
+fn parse_selector(selector: &[u8]) -> Option<Vec<(u32, u32, i32)>> {
+    if selector.len() < U32_SEL_LEN {
+        return None;
+    }
+    let count = selector[2] as usize;
+    let keys = selector[U32_SEL_LEN..]
+        .chunks_exact(U32_KEY_LEN)
+        .take(count)
+        .map(|key| {
+            let mask = u32::from_be_bytes([key[0], key[1], key[2], key[3]]);
+            let value = u32::from_be_bytes([key[4], key[5], key[6], key[7]]);
+            let offset = i32::from_ne_bytes([key[8], key[9], key[10], key[11]]);
+            (value, mask, offset)
+        })
+        .collect::<Vec<_>>();
+    (keys.len() == count).then_some(keys)
+}
+
+fn tcmsg(ifindex: i32, handle: u32, parent: u32, info: u32) -> Vec<u8> {
+    let mut v = Vec::with_capacity(TCMSG_LEN);
+    // family and padding
+    v.extend_from_slice(&[libc::AF_UNSPEC as u8, 0, 0, 0]);
+    v.extend_from_slice(&ifindex.to_ne_bytes());
+    v.extend_from_slice(&handle.to_ne_bytes());
+    v.extend_from_slice(&parent.to_ne_bytes());
+    v.extend_from_slice(&info.to_ne_bytes());
+    v
+}
+
+fn ifinfomsg(index: i32, flags: u32, change: u32) -> Vec<u8> {
+    let mut v = Vec::with_capacity(IFINFOMSG_LEN);
+    // family, padding and device type
+    v.extend_from_slice(&[libc::AF_UNSPEC as u8, 0, 0, 0]);
+    v.extend_from_slice(&index.to_ne_bytes());
+    v.extend_from_slice(&flags.to_ne_bytes());
+    v.extend_from_slice(&change.to_ne_bytes());
+    v
+}
+
+/// A link of a RTM_NEWLINK message from a dump.
+#[derive(Debug)]
+pub struct Link {
+    pub name: String,
+    /// IFLA_INFO_KIND, like `ifb` or `bridge`, `None` for physical devices
+    pub kind: Option<String>,
+    pub alias: Option<String>,
+}
+
+fn string(value: &[u8]) -> String {
+    String::from_utf8_lossy(value)
+        .trim_end_matches('\0')
+        .to_owned()
+}
+
+impl Link {
+    fn parse(payload: &[u8]) -> Option<Self> {
+        if payload.len() < IFINFOMSG_LEN {
+            return None;
+        }
+
+        let mut link = Link {
+            name: String::new(),
+            kind: None,
+            alias: None,
+        };
+        for (kind, value) in attributes(&payload[IFINFOMSG_LEN..]) {
+            match kind {
+                IFLA_IFNAME => link.name = string(value),
+                IFLA_IFALIAS => link.alias = Some(string(value)).filter(|a| !a.is_empty()),
+                IFLA_LINKINFO => {
+                    link.kind = attributes(value)
+                        .find(|(kind, _)| *kind == IFLA_INFO_KIND)
+                        .map(|(_, value)| string(value))
+                }
+                _ => {}
+            }
+        }
+
+        Some(link)
+    }
+}
+
+struct Socket {
+    fd: OwnedFd,
+    sequence: u32,
+}
+
+impl Socket {
+    fn open() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        let result = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // error messages from the kernel, like the ones tc prints, and no
+        // copy of the request in acks. Old kernels don't know them.
+        for option in [NETLINK_EXT_ACK, NETLINK_CAP_ACK] {
+            let enable: libc::c_int = 1;
+            unsafe {
+                libc::setsockopt(
+                    fd.as_raw_fd(),
+                    SOL_NETLINK,
+                    option,
+                    &enable as *const libc::c_int as *const libc::c_void,
+                    std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+                );
+            }
+        }
+
+        Ok(Socket { fd, sequence: 0 })
+    }
+
+    fn send(&mut self, kind: u16, flags: u16, payload: &[u8]) -> io::Result<u32> {
+        self.sequence += 1;
+
+        let len = NLMSG_HDRLEN + payload.len();
+        let mut message = Vec::with_capacity(len);
+        message.extend_from_slice(&(len as u32).to_ne_bytes());
+        message.extend_from_slice(&kind.to_ne_bytes());
+        message.extend_from_slice(&(flags | NLM_F_REQUEST).to_ne_bytes());
+        message.extend_from_slice(&self.sequence.to_ne_bytes());
+        // port id, 0 is the kernel
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        message.extend_from_slice(payload);
+
+        let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        let sent = unsafe {
+            libc::sendto(
+                self.fd.as_raw_fd(),
+                message.as_ptr() as *const libc::c_void,
+                message.len(),
+                0,
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(self.sequence)
+    }
+
+    fn receive(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let received = unsafe {
+            libc::recv(
+                self.fd.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if received < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(received as usize)
+        }
+    }
+
+    /// Send a request and collect the payloads of the replies to it, until
+    /// the ack of a request or the end of a dump.
+    fn request(&mut self, kind: u16, flags: u16, payload: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+        let sequence = self
+            .send(kind, flags, payload)
+            .map_err(|e| anyhow::anyhow!("Netlink send error: {}", e))?;
+
+        let mut replies = Vec::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let received = self
+                .receive(&mut buf)
+                .map_err(|e| anyhow::anyhow!("Netlink receive error: {}", e))?;
+
+            let mut messages = &buf[..received];
+            while messages.len() >= NLMSG_HDRLEN {
+                let len = u32::from_ne_bytes([messages[0], messages[1], messages[2], messages[3]])
+                    as usize;
+                if len < NLMSG_HDRLEN || len > messages.len() {
+                    return Err(anyhow::anyhow!("Truncated netlink message"));
+                }
+                let message_kind = u16::from_ne_bytes([messages[4], messages[5]]);
+                let message_flags = u16::from_ne_bytes([messages[6], messages[7]]);
+                let message_sequence =
+                    u32::from_ne_bytes([messages[8], messages[9], messages[10], messages[11]]);
+                let message_payload = &messages[NLMSG_HDRLEN..len];
+                messages = &messages[align(len).min(messages.len())..];
+
+                if message_sequence != sequence {
+                    continue;
+                }
+
+                match message_kind {
+                    NLMSG_ERROR => {
+                        return match error(message_flags, message_payload) {
+                            Some(e) => Err(e),
+                            None => Ok(replies),
+                        }
+                    }
+                    NLMSG_DONE => return Ok(replies),
+                    _ => {
+                        replies.push(message_payload.to_vec());
+                        if message_flags & NLM_F_MULTI == 0 && flags & NLM_F_ACK == 0 {
+                            return Ok(replies);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The error of a NLMSG_ERROR message, `None` for an ack.
+fn error(flags: u16, payload: &[u8]) -> Option<anyhow::Error> {
+    if payload.len() < 4 {
+        return Some(anyhow::anyhow!("Truncated netlink error"));
+    }
+    let errno = -i32::from_ne_bytes([payload[0], payload[1], payload[2], payload[3]]);
+    if errno == 0 {
+        return None;
+    }
+
+    let error = io::Error::from_raw_os_error(errno);
+    // the request is not echoed back with NETLINK_CAP_ACK, only its header
+    let message = if flags & NLM_F_ACK_TLVS != 0 && payload.len() > 4 + NLMSG_HDRLEN {
+        attributes(&payload[4 + NLMSG_HDRLEN..])
+            .find(|(kind, _)| *kind == NLMSGERR_ATTR_MSG)
+            .map(|(_, value)| {
+                String::from_utf8_lossy(value)
+                    .trim_end_matches('\0')
+                    .to_owned()
+            })
+    } else {
+        None
+    };
+
+    Some(match message {
+        Some(message) => anyhow::anyhow!("Netlink error: {} ({})", message, error),
+        None => anyhow::anyhow!("Netlink error: {}", error),
+    })
+}
+
+pub fn interface_index(name: &str) -> anyhow::Result<i32> {
+    let c_name =
+        CString::new(name).map_err(|_| anyhow::anyhow!("Invalid interface name: '{}'", name))?;
+    match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+        0 => Err(anyhow::anyhow!("Cannot find device \"{}\"", name)),
+        index => Ok(index as i32),
+    }
+}
+
+pub fn interface_name(index: i32) -> Option<String> {
+    let mut buf = [0 as libc::c_char; libc::IF_NAMESIZE];
+    let name = unsafe { libc::if_indextoname(index as libc::c_uint, buf.as_mut_ptr()) };
+    if name.is_null() {
+        None
+    } else {
+        Some(
+            unsafe { CStr::from_ptr(name) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+/// `tc qdisc replace dev DEV parent PARENT [ handle HANDLE ] KIND OPTIONS`,
+/// a handle of 0 lets the kernel pick one.
+pub fn replace_qdisc(
+    ifindex: i32,
+    parent: u32,
+    handle: u32,
+    kind: &str,
+    options: Vec<u8>,
+) -> anyhow::Result<()> {
+    let mut payload = tcmsg(ifindex, handle, parent, 0);
+    let mut attributes = Attributes::new();
+    attributes
+        .put_string(TCA_KIND, kind)
+        .put(TCA_OPTIONS, &options);
+    payload.extend_from_slice(&attributes.into_bytes());
+
+    Socket::open()?.request(
+        RTM_NEWQDISC,
+        NLM_F_CREATE | NLM_F_REPLACE | NLM_F_ACK,
+        &payload,
+    )?;
+
+    Ok(())
+}
+
+/// `tc qdisc del dev DEV parent PARENT [ handle HANDLE ] KIND`, which fails
+/// if the qdisc there is of another kind, or has another handle.
+pub fn delete_qdisc(ifindex: i32, parent: u32, handle: u32, kind: &str) -> anyhow::Result<()> {
+    let mut payload = tcmsg(ifindex, handle, parent, 0);
+    let mut attributes = Attributes::new();
+    attributes.put_string(TCA_KIND, kind);
+    payload.extend_from_slice(&attributes.into_bytes());
+
+    Socket::open()?.request(RTM_DELQDISC, NLM_F_ACK, &payload)?;
+
+    Ok(())
+}
+
+/// `tc qdisc show`
+pub fn dump_qdiscs() -> anyhow::Result<Vec<Qdisc>> {
+    let replies = Socket::open()?.request(RTM_GETQDISC, NLM_F_DUMP, &tcmsg(0, 0, 0, 0))?;
+
+    Ok(replies
+        .iter()
+        .filter_map(|payload| Qdisc::parse(payload))
+        .collect())
+}
+
+/// `ip link add name NAME type KIND`
+pub fn add_link(name: &str, kind: &str) -> anyhow::Result<()> {
+    let mut payload = ifinfomsg(0, 0, 0);
+    let mut info = Attributes::new();
+    info.put_string(IFLA_INFO_KIND, kind);
+    let mut attributes = Attributes::new();
+    attributes
+        .put_string(IFLA_IFNAME, name)
+        .put(IFLA_LINKINFO, &info.into_bytes());
+    payload.extend_from_slice(&attributes.into_bytes());
+
+    Socket::open()?.request(RTM_NEWLINK, NLM_F_CREATE | NLM_F_EXCL | NLM_F_ACK, &payload)?;
+
+    Ok(())
+}
+
+/// `ip link set dev DEV alias ALIAS up`
+pub fn set_link_up(index: i32, alias: &str) -> anyhow::Result<()> {
+    let up = libc::IFF_UP as u32;
+    let mut payload = ifinfomsg(index, up, up);
+    let mut attributes = Attributes::new();
+    attributes.put(IFLA_IFALIAS, alias.as_bytes());
+    payload.extend_from_slice(&attributes.into_bytes());
+
+    Socket::open()?.request(RTM_NEWLINK, NLM_F_ACK, &payload)?;
+
+    Ok(())
+}
+
+/// `ip link del dev DEV`
+pub fn delete_link(index: i32) -> anyhow::Result<()> {
+    Socket::open()?.request(RTM_DELLINK, NLM_F_ACK, &ifinfomsg(index, 0, 0))?;
+
+    Ok(())
+}
+
+/// `ip link show`
+pub fn dump_links() -> anyhow::Result<Vec<Link>> {
+    let replies = Socket::open()?.request(RTM_GETLINK, NLM_F_DUMP, &ifinfomsg(0, 0, 0))?;
+
+    Ok(replies
+        .iter()
+        .filter_map(|payload| Link::parse(payload))
+        .collect())
+}
+
+/// `ip route get ADDRESS`, the index of the interface traffic to an address
+/// leaves through
+pub fn route(address: IpAddr) -> anyhow::Result<i32> {
+    let (family, octets) = match address {
+        IpAddr::V4(address) => (libc::AF_INET, address.octets().to_vec()),
+        IpAddr::V6(address) => (libc::AF_INET6, address.octets().to_vec()),
+    };
+    // family, lengths of destination and source, tos, table, protocol,
+    // scope, type and flags
+    let mut payload = vec![0u8; RTMSG_LEN];
+    payload[0] = family as u8;
+    payload[1] = octets.len() as u8 * 8;
+    let mut destination = Attributes::new();
+    destination.put(RTA_DST, &octets);
+    payload.extend_from_slice(&destination.into_bytes());
+
+    let replies = Socket::open()?.request(RTM_GETROUTE, 0, &payload)?;
+    replies
+        .first()
+        .and_then(|reply| attributes(reply.get(RTMSG_LEN..)?).find(|(kind, _)| *kind == RTA_OIF))
+        .and_then(|(_, value)| Some(i32::from_ne_bytes(value.try_into().ok()?)))
+        .ok_or_else(|| anyhow::anyhow!("No route to {}", address))
+}
+
+fn add_filter(
+    ifindex: i32,
+    parent: u32,
+    priority: u16,
+    protocol: u16,
+    kind: &str,
+    options: Attributes,
+) -> anyhow::Result<()> {
+    let info = ((priority as u32) << 16) | protocol.to_be() as u32;
+    let mut payload = tcmsg(ifindex, 0, parent, info);
+    let mut attributes = Attributes::new();
+    attributes
+        .put_string(TCA_KIND, kind)
+        .put(TCA_OPTIONS, &options.into_bytes());
+    payload.extend_from_slice(&attributes.into_bytes());
+
+    Socket::open()?.request(
+        RTM_NEWTFILTER,
+        NLM_F_CREATE | NLM_F_EXCL | NLM_F_ACK,
+        &payload,
+    )?;
+
+    Ok(())
+}
+
+/// `tc filter add dev DEV parent ffff: protocol all prio 1 u32 match u32 0 0
+/// action mirred egress redirect dev TARGET`
+pub fn add_redirect_filter(ifindex: i32, parent: u32, target: i32) -> anyhow::Result<()> {
+    // struct tc_mirred
+    let mut mirred = Vec::with_capacity(28);
+    for value in [0, 0, TC_ACT_STOLEN, 0, 0, TCA_EGRESS_REDIR, target] {
+        mirred.extend_from_slice(&value.to_ne_bytes());
+    }
+
+    let mut mirred_options = Attributes::new();
+    mirred_options.put(TCA_MIRRED_PARMS, &mirred);
+    let mut action = Attributes::new();
+    action
+        .put_string(TCA_ACT_KIND, "mirred")
+        .put(TCA_ACT_OPTIONS, &mirred_options.into_bytes());
+    // actions are nested by their order, starting at 1
+    let mut actions = Attributes::new();
+    actions.put(1, &action.into_bytes());
+
+    let mut options = Attributes::new();
+    options
+        .put(TCA_U32_SEL, &selector(&[(0, 0, 0)]))
+        .put(TCA_U32_ACT, &actions.into_bytes());
+
+    add_filter(ifindex, parent, 1, ETH_P_ALL, "u32", options)
+}
+
+/// `tc filter add dev DEV parent PARENT protocol PROTOCOL prio PRIORITY u32
+/// match u32 VALUE MASK at OFFSET ... flowid CLASSID`
+pub fn add_u32_filter(ifindex: i32, parent: u32, filter: &U32) -> anyhow::Result<()> {
+    // like tc, a filter without keys has one matching everything
+    let keys = if filter.keys.is_empty() {
+        vec![(0, 0, 0)]
+    } else {
+        filter.keys.clone()
+    };
+
+    let mut options = Attributes::new();
+    options
+        .put_u32(TCA_U32_CLASSID, filter.classid)
+        .put(TCA_U32_SEL, &selector(&keys));
+
+    add_filter(
+        ifindex,
+        parent,
+        filter.priority,
+        filter.protocol,
+        "u32",
+        options,
+    )
+}
+
+/// `tc filter del dev DEV parent PARENT prio PRIORITY`
+pub fn delete_filters(ifindex: i32, parent: u32, priority: u16) -> anyhow::Result<()> {
+    let payload = tcmsg(ifindex, 0, parent, (priority as u32) << 16);
+    Socket::open()?.request(RTM_DELTFILTER, NLM_F_ACK, &payload)?;
+
+    Ok(())
+}
+
+/// `tc filter show dev DEV parent PARENT`, keeping the u32 ones
+pub fn dump_u32_filters(ifindex: i32, parent: u32) -> anyhow::Result<Vec<U32>> {
+    let replies =
+        Socket::open()?.request(RTM_GETTFILTER, NLM_F_DUMP, &tcmsg(ifindex, 0, parent, 0))?;
+
+    Ok(replies
+        .iter()
+        .filter_map(|payload| U32::parse(payload))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_attributes() {
+        let mut buf = Attributes::with_header(&[1, 2, 3, 4, 5, 6]);
+        buf.put_string(TCA_KIND, "netem").put_u32(TCA_OPTIONS, 42);
+        let bytes = buf.into_bytes();
+
+        // the header is padded to 4 bytes
+        assert_eq!(&bytes[..8], &[1, 2, 3, 4, 5, 6, 0, 0]);
+
+        let parsed = attributes(&bytes[8..]).collect::<Vec<_>>();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0], (TCA_KIND, &b"netem\0"[..]));
+        assert_eq!(parsed[1], (TCA_OPTIONS, &42u32.to_ne_bytes()[..]));
+
+        // a truncated attribute ends the iteration
+        assert_eq!(attributes(&bytes[8..bytes.len() - 1]).count(), 1);
+    }
+
+    #[test]
+    fn test_selector() {
+        let keys = vec![(0x0a00_0100, 0xffff_ff00, 12), (22, 0xffff, 20)];
+        let selector = selector(&keys);
+
+        assert_eq!(selector.len(), U32_SEL_LEN + 2 * U32_KEY_LEN);
+        // masks and values are in network byte order
+        assert_eq!(&selector[16..24], &[0xff, 0xff, 0xff, 0, 10, 0, 1, 0]);
+        assert_eq!(parse_selector(&selector), Some(keys));
+        assert_eq!(parse_selector(&selector[..40]), None);
+    }
+}